@@ -0,0 +1,124 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use aws_sdk_s3::config::{Credentials, Region};
+use aws_sdk_s3::Client;
+
+use crate::config::StorageConfig;
+use crate::utils::timestamp;
+
+/// An object synced by a job, keyed relative to the job's prefix.
+#[derive(Debug, Clone)]
+pub struct Artifact {
+    pub key: String,
+    pub size: i64,
+}
+
+/// Returns a fresh key prefix for the artifacts of a new job.
+#[must_use]
+pub fn prefix(storage: &StorageConfig, job_name: &str) -> String {
+    format!(
+        "{}/{job_name}-{}",
+        storage.prefix.trim_end_matches('/'),
+        timestamp()
+    )
+}
+
+async fn client(storage: &StorageConfig) -> Client {
+    let shared_config = aws_config::from_env()
+        .region(Region::new(storage.region.clone()))
+        .load()
+        .await;
+    let mut builder = aws_sdk_s3::config::Builder::from(&shared_config);
+    if let Some(endpoint) = &storage.endpoint {
+        builder = builder.endpoint_url(endpoint).force_path_style(true);
+    }
+    if let (Some(access_key_id), Some(secret_access_key)) =
+        (&storage.access_key_id, &storage.secret_access_key)
+    {
+        builder = builder.credentials_provider(Credentials::new(
+            access_key_id,
+            secret_access_key,
+            None,
+            None,
+            "ffs",
+        ));
+    }
+    Client::from_conf(builder.build())
+}
+
+/// Lists the artifacts stored under `prefix`.
+///
+/// # Errors
+///
+/// Returns an error if the bucket cannot be listed.
+pub async fn list(
+    storage: &StorageConfig,
+    prefix: &str,
+) -> Result<Vec<Artifact>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = client(storage).await;
+    let root = format!("{prefix}/");
+    let mut pages = client
+        .list_objects_v2()
+        .bucket(&storage.bucket)
+        .prefix(&root)
+        .into_paginator()
+        .send();
+    let mut artifacts = Vec::new();
+    while let Some(page) = pages.next().await {
+        for object in page?.contents() {
+            let Some(key) = object.key().and_then(|key| key.strip_prefix(&root)) else {
+                continue;
+            };
+            artifacts.push(Artifact {
+                key: key.to_string(),
+                size: object.size().unwrap_or_default(),
+            });
+        }
+    }
+    Ok(artifacts)
+}
+
+/// Downloads every artifact stored under `prefix` into `destination`, returning
+/// how many were written.
+///
+/// # Errors
+///
+/// Returns an error if an artifact cannot be downloaded or written, or its key
+/// would escape `destination`.
+pub async fn pull(
+    storage: &StorageConfig,
+    prefix: &str,
+    destination: &Path,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let artifacts = list(storage, prefix).await?;
+    let client = client(storage).await;
+    for artifact in &artifacts {
+        let path = local_path(destination, &artifact.key)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let object = client
+            .get_object()
+            .bucket(&storage.bucket)
+            .key(format!("{prefix}/{}", artifact.key))
+            .send()
+            .await?;
+        fs::write(&path, object.body.collect().await?.into_bytes())?;
+    }
+    Ok(artifacts.len())
+}
+
+fn local_path(destination: &Path, key: &str) -> Result<PathBuf, String> {
+    let relative = Path::new(key);
+    if relative
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_)))
+    {
+        return Err(format!(
+            "refusing to write artifact outside {}: {key}",
+            destination.display()
+        ));
+    }
+    Ok(destination.join(relative))
+}
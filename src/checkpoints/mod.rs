@@ -0,0 +1,74 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::config::SshConfig;
+use crate::providers::JOB_DIR;
+use crate::ssh::{self, Connection};
+
+const CHECKPOINTS_DIR: &str = ".ffs/checkpoints";
+const SSH_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+const SSH_RETRIES: u32 = 60;
+
+/// Returns where the latest checkpoint of a job is stored locally.
+///
+/// # Panics
+///
+/// Panics if the HOME environment variable is not set.
+#[must_use]
+pub fn path(job_id: &str) -> PathBuf {
+    let home = std::env::var("HOME").unwrap();
+    PathBuf::from(format!("{home}/{CHECKPOINTS_DIR}/{job_id}.tar.gz"))
+}
+
+/// Archives the corpus directories of a job, relative to `/root`, into its local
+/// checkpoint, replacing the previous one.
+///
+/// # Errors
+///
+/// Returns an error if the archive cannot be created or downloaded.
+pub async fn save(
+    job_id: &str,
+    ip: &str,
+    ssh: &SshConfig,
+    corpus_dirs: &[String],
+) -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync>> {
+    let path = path(job_id);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let partial = path.with_extension("partial");
+    let command = format!(
+        "cd /root && tar czf - --ignore-failed-read {}",
+        corpus_dirs.join(" ")
+    );
+    let (ip, ssh, target) = (ip.to_string(), ssh.clone(), partial.clone());
+    let job_id = job_id.to_string();
+    tokio::task::spawn_blocking(move || {
+        Connection::open(&job_id, &ip, &ssh)?.download(&command, &target)
+    })
+    .await??;
+    fs::rename(&partial, &path)?;
+    Ok(path)
+}
+
+/// Uploads the checkpoint of `from_job_id` to the new job `job_id` once it
+/// accepts SSH connections, and releases the campaign waiting for it.
+///
+/// # Errors
+///
+/// Returns an error if the job never becomes reachable or the upload fails.
+pub async fn restore(
+    from_job_id: &str,
+    job_id: &str,
+    ip: &str,
+    ssh: &SshConfig,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let checkpoint = path(from_job_id);
+    let connection =
+        ssh::wait_until_reachable(job_id, ip, ssh, SSH_RETRIES, SSH_RETRY_INTERVAL).await?;
+
+    let command = format!("mkdir -p {JOB_DIR} && tar xzf - -C /root && touch {JOB_DIR}/restored");
+    tokio::task::spawn_blocking(move || connection.upload(&checkpoint, &command)).await??;
+    Ok(())
+}
@@ -0,0 +1,261 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use super::ConfigError;
+use crate::database::{self, Database};
+
+/// Name of the project configuration file discovered from the working directory.
+pub const PROJECT_FILENAME: &str = "ffs.toml";
+
+/// Built-in defaults, the lowest layer.
+const DEFAULTS: &[(&str, &str)] = &[
+    ("provider", "hetzner"),
+    ("profile", "default"),
+    ("toolchain", "default"),
+    ("ssh.user", "root"),
+    ("ssh.port", "22"),
+    ("ssh.connect_timeout", "10s"),
+    ("ssh.keepalive", "30s"),
+    ("ssh.retries", "3"),
+    ("ssh.agent", "true"),
+    ("hetzner.server_type", "cpx51"),
+    ("hetzner.location", "fsn1"),
+    ("hetzner.image", "ubuntu-24.04"),
+    ("aws.region", "us-east-1"),
+    ("aws.instance_type", "c6i.4xlarge"),
+    ("aws.spot", "false"),
+    ("aws.spot_fallback_on_demand", "true"),
+    ("campaign.corpus_dirs", "corpus"),
+    ("storage.region", "us-east-1"),
+    ("storage.prefix", "ffs"),
+    ("storage.interval", "10m"),
+    ("gc.ttl", "48h"),
+    ("gc.provisioning_timeout", "30m"),
+];
+
+/// Keys without a default that may still be set from the environment.
+const KNOWN_KEYS: &[&str] = &[
+    "ssh_key_path",
+    "ssh_key_name",
+    "user_data",
+    "ssh.passphrase",
+    "ssh.jump_host",
+    "ssh.proxy_command",
+    "hetzner.token",
+    "hetzner.firewalls",
+    "aws.ami",
+    "aws.subnet_id",
+    "aws.security_group_ids",
+    "aws.iam_instance_profile",
+    "aws.spot_max_price",
+    "aws.fallback_instance_types",
+    "aws.fallback_subnet_ids",
+    "budget.monthly",
+    "budget.job",
+    "storage.bucket",
+    "storage.endpoint",
+    "storage.access_key_id",
+    "storage.secret_access_key",
+    "storage.paths",
+];
+
+/// Flat keys written by earlier versions and the sectioned keys replacing them.
+const LEGACY_KEYS: &[(&str, &str)] = &[
+    ("hcloud_token", "hetzner.token"),
+    ("server_type", "hetzner.server_type"),
+    ("location", "hetzner.location"),
+    ("image", "hetzner.image"),
+];
+
+/// Well-known environment variables that override a config key, e.g. for CI.
+const ENV_OVERRIDES: &[(&str, &str)] = &[
+    ("hetzner.token", "HCLOUD_TOKEN"),
+    ("aws.region", "AWS_REGION"),
+];
+
+static CLI_OVERRIDES: OnceLock<CliOverrides> = OnceLock::new();
+
+#[derive(Debug, Default)]
+struct CliOverrides {
+    project: Option<PathBuf>,
+    values: Vec<(String, String)>,
+}
+
+/// Records the `--config` and `--set key=value` flags so that every later
+/// [`Settings::load`] applies them. Only the first call has an effect.
+pub fn set_cli_overrides(project: Option<PathBuf>, values: Vec<(String, String)>) {
+    let _ = CLI_OVERRIDES.set(CliOverrides { project, values });
+}
+
+/// Layer an effective config value came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    Default,
+    Global(String),
+    Project(PathBuf),
+    Env(String),
+    Cli,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default => write!(f, "default"),
+            Self::Global(path) => write!(f, "global ({path})"),
+            Self::Project(path) => write!(f, "project ({})", path.display()),
+            Self::Env(var) => write!(f, "env ({var})"),
+            Self::Cli => write!(f, "cli (--set)"),
+        }
+    }
+}
+
+/// Effective configuration resolved from, in increasing precedence: built-in
+/// defaults, `~/.ffs/database.toml`, the project `ffs.toml`, environment
+/// variables and CLI flags.
+#[derive(Debug, Default)]
+pub struct Settings {
+    values: HashMap<String, (String, Origin)>,
+}
+
+impl Settings {
+    /// Resolves every layer, discovering the project file from the working
+    /// directory unless `--config` was given.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the project file cannot be read or parsed.
+    pub fn load() -> Result<Self, ConfigError> {
+        let cli = CLI_OVERRIDES.get();
+        let project = match cli.and_then(|cli| cli.project.clone()) {
+            Some(path) => Some(path),
+            None => std::env::current_dir().ok().and_then(|cwd| discover(&cwd)),
+        };
+        Self::load_with(project.as_deref())
+    }
+
+    /// Resolves every layer using the given project file, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the project file cannot be read or parsed.
+    pub fn load_with(project: Option<&Path>) -> Result<Self, ConfigError> {
+        let database = Database::new();
+        let project = project
+            .map(|path| read_project(path).map(|entries| (path.to_path_buf(), entries)))
+            .transpose()?;
+        let cli = CLI_OVERRIDES
+            .get()
+            .map(|cli| cli.values.as_slice())
+            .unwrap_or_default();
+        Ok(Self::from_layers(&database, project, cli))
+    }
+
+    /// Builds the settings from an already loaded database and project file.
+    #[must_use]
+    pub fn from_layers(
+        database: &Database,
+        project: Option<(PathBuf, HashMap<String, String>)>,
+        cli: &[(String, String)],
+    ) -> Self {
+        let mut settings = Self::default();
+        settings.apply(
+            DEFAULTS
+                .iter()
+                .map(|(k, v)| ((*k).to_string(), (*v).to_string())),
+            &Origin::Default,
+        );
+        settings.apply(
+            migrate(database.entries().clone()),
+            &Origin::Global(database.path().to_string()),
+        );
+        if let Some((path, entries)) = project {
+            settings.apply(migrate(entries), &Origin::Project(path));
+        }
+        settings.apply_env();
+        settings.apply(cli.iter().cloned(), &Origin::Cli);
+        settings
+    }
+
+    /// Returns the effective value of `key`.
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.values.get(key).map(|(value, _)| value.clone())
+    }
+
+    /// Returns the layer the effective value of `key` came from.
+    #[must_use]
+    pub fn origin(&self, key: &str) -> Option<&Origin> {
+        self.values.get(key).map(|(_, origin)| origin)
+    }
+
+    /// Returns every effective value with its origin, sorted by key.
+    #[must_use]
+    pub fn entries(&self) -> BTreeMap<&str, (&str, &Origin)> {
+        self.values
+            .iter()
+            .map(|(key, (value, origin))| (key.as_str(), (value.as_str(), origin)))
+            .collect()
+    }
+
+    fn apply(&mut self, entries: impl IntoIterator<Item = (String, String)>, origin: &Origin) {
+        for (key, value) in entries {
+            if !value.is_empty() {
+                self.values.insert(key, (value, origin.clone()));
+            }
+        }
+    }
+
+    /// Applies `HCLOUD_TOKEN`-style overrides and `FFS_<KEY>` variables, with dots
+    /// in the key replaced by underscores, for every known key.
+    fn apply_env(&mut self) {
+        let mut keys: Vec<String> = self.values.keys().cloned().collect();
+        keys.extend(KNOWN_KEYS.iter().map(ToString::to_string));
+        keys.sort();
+        keys.dedup();
+        for key in keys {
+            let well_known = ENV_OVERRIDES
+                .iter()
+                .filter(|(name, _)| *name == key)
+                .map(|(_, var)| (*var).to_string());
+            let generic = format!("FFS_{}", key.replace('.', "_").to_uppercase());
+            for var in well_known.chain(std::iter::once(generic)) {
+                if let Ok(value) = std::env::var(&var) {
+                    if !value.is_empty() {
+                        self.values.insert(key.clone(), (value, Origin::Env(var)));
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Walks up from `dir` looking for a project `ffs.toml`.
+#[must_use]
+pub fn discover(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|ancestor| ancestor.join(PROJECT_FILENAME))
+        .find(|candidate| candidate.is_file())
+}
+
+fn read_project(path: &Path) -> Result<HashMap<String, String>, ConfigError> {
+    let error = |reason: String| ConfigError::File {
+        path: path.display().to_string(),
+        reason,
+    };
+    let contents = fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+    database::parse(&contents).map_err(|e| error(e.to_string()))
+}
+
+/// Renames flat keys written by earlier versions to their sectioned equivalent.
+fn migrate(mut entries: HashMap<String, String>) -> HashMap<String, String> {
+    for (legacy, key) in LEGACY_KEYS {
+        if let Some(value) = entries.remove(*legacy) {
+            entries.entry((*key).to_string()).or_insert(value);
+        }
+    }
+    entries
+}
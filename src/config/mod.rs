@@ -1,14 +1,45 @@
-use serde::Deserialize;
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
 
-use crate::database::Database;
-#[derive(Debug, Deserialize)]
+use crate::secrets;
+use crate::utils::parse_duration;
+
+mod layers;
+
+pub use layers::{discover, set_cli_overrides, Origin, Settings, PROJECT_FILENAME};
+
+/// Error returned when a profile is missing a required key or holds an invalid value.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    MissingKey(String),
+    InvalidValue { key: String, value: String },
+    Secret { key: String, reason: String },
+    File { path: String, reason: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingKey(key) => write!(f, "missing required config key `{key}`"),
+            Self::InvalidValue { key, value } => {
+                write!(f, "invalid value `{value}` for config key `{key}`")
+            }
+            Self::Secret { key, reason } => {
+                write!(f, "cannot resolve secret for config key `{key}`: {reason}")
+            }
+            Self::File { path, reason } => write!(f, "cannot read config file {path}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Settings shared by every provider.
+#[derive(Debug)]
 pub struct Config {
-    pub hcloud_api_token: String,
     pub ssh_key_path: String,
     pub ssh_key_name: String,
-    pub image: String,
-    pub server_type: String,
-    pub location: String,
     pub user_data: String,
 }
 
@@ -19,34 +50,458 @@ impl Default for Config {
 }
 
 impl Config {
+    /// Loads the shared settings from every config layer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the project config file cannot be read.
     #[must_use]
     pub fn new() -> Self {
-        let database = Database::new();
-        let hcloud_api_token = database.get("hcloud_token").unwrap_or_default();
-        let ssh_key_path = database.get("ssh_key_path").unwrap_or_default();
-        let ssh_key_name = database.get("ssh_key_name").unwrap_or_default();
-        let image = database.get("image").unwrap_or_default();
-        let server_type = database.get("server_type").unwrap_or_default();
-        let location = database.get("location").unwrap_or_default();
-        let user_data = database.get("user_data").unwrap_or_default();
+        Self::from_settings(&Settings::load().unwrap_or_else(|e| panic!("{e}")))
+    }
+
+    #[must_use]
+    pub fn from_settings(settings: &Settings) -> Self {
+        let ssh_key_path = settings.get("ssh_key_path").unwrap_or_default();
+        let ssh_key_name = settings.get("ssh_key_name").unwrap_or_default();
+        let user_data = settings.get("user_data").unwrap_or_default();
 
         Self {
-            hcloud_api_token,
             ssh_key_path,
             ssh_key_name,
-            image,
-            server_type,
-            location,
             user_data,
         }
     }
 }
 
-/// Loads configuration from a config file path.
+/// Resolves an arbitrary config key such as `rpc_url` or `deploy_key`, following
+/// environment overrides and secret references.
+///
+/// # Errors
+///
+/// Returns an error if the config cannot be loaded or the value references a
+/// secret that cannot be read.
+pub fn secret(key: &str) -> Result<Option<String>, ConfigError> {
+    lookup(&Settings::load()?, key)
+}
+
+/// Settings read from the `[hetzner]` section of the profile.
+#[derive(Debug)]
+pub struct HetznerConfig {
+    pub api_token: String,
+    pub server_type: String,
+    pub location: String,
+    pub image: String,
+    /// Snapshot baked for the configured toolchain, used instead of `image`.
+    pub baked_image: Option<String>,
+    pub firewalls: Vec<i64>,
+}
+
+impl HetznerConfig {
+    /// Loads the Hetzner section from every config layer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `hetzner.token` is missing or a firewall id is not numeric.
+    pub fn load() -> Result<Self, ConfigError> {
+        Self::from_settings(&Settings::load()?)
+    }
+
+    /// Reads the Hetzner section from the given settings.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `hetzner.token` is missing or a firewall id is not numeric.
+    pub fn from_settings(settings: &Settings) -> Result<Self, ConfigError> {
+        let api_token = required(settings, "hetzner.token")?;
+        let server_type = required(settings, "hetzner.server_type")?;
+        let location = required(settings, "hetzner.location")?;
+        let image = required(settings, "hetzner.image")?;
+        let baked_image = baked_image(settings, "hetzner")?;
+        let firewalls = list(settings, "hetzner.firewalls")?
+            .into_iter()
+            .map(|id| {
+                id.parse::<i64>().map_err(|_| ConfigError::InvalidValue {
+                    key: "hetzner.firewalls".to_string(),
+                    value: id,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            api_token,
+            server_type,
+            location,
+            image,
+            baked_image,
+            firewalls,
+        })
+    }
+}
+
+/// Spot market settings for EC2 instances.
+#[derive(Debug, Default)]
+pub struct SpotOptions {
+    pub enabled: bool,
+    pub max_price: Option<String>,
+    /// Whether to launch on demand when no spot capacity is available.
+    pub fallback_on_demand: bool,
+}
+
+/// Settings read from the `[aws]` section of the profile.
+#[derive(Debug)]
+pub struct AwsConfig {
+    pub region: String,
+    pub ami: String,
+    /// AMI baked for the configured toolchain, used instead of `ami`.
+    pub baked_image: Option<String>,
+    pub instance_type: String,
+    /// Instance types tried, in order, when `instance_type` has no capacity.
+    pub fallback_instance_types: Vec<String>,
+    pub subnet_id: Option<String>,
+    /// Subnets (usually in other availability zones) tried when `subnet_id` has no capacity.
+    pub fallback_subnet_ids: Vec<String>,
+    pub security_group_ids: Vec<String>,
+    pub iam_instance_profile: Option<String>,
+    pub spot: SpotOptions,
+}
+
+impl AwsConfig {
+    /// Loads the AWS section from every config layer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `aws.ami` is missing or a spot flag is not a boolean.
+    pub fn load() -> Result<Self, ConfigError> {
+        Self::from_settings(&Settings::load()?)
+    }
+
+    /// Reads the AWS section from the given settings.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `aws.ami` is missing or a spot flag is not a boolean.
+    pub fn from_settings(settings: &Settings) -> Result<Self, ConfigError> {
+        let region = required(settings, "aws.region")?;
+        let ami = required(settings, "aws.ami")?;
+        let baked_image = baked_image(settings, "aws")?;
+        let instance_type = required(settings, "aws.instance_type")?;
+        let fallback_instance_types = list(settings, "aws.fallback_instance_types")?;
+        let subnet_id = lookup(settings, "aws.subnet_id")?;
+        let fallback_subnet_ids = list(settings, "aws.fallback_subnet_ids")?;
+        let security_group_ids = list(settings, "aws.security_group_ids")?;
+        let iam_instance_profile = lookup(settings, "aws.iam_instance_profile")?;
+        let enabled = boolean(settings, "aws.spot")?;
+        let fallback_on_demand = boolean(settings, "aws.spot_fallback_on_demand")?;
+        let max_price = lookup(settings, "aws.spot_max_price")?;
+
+        Ok(Self {
+            region,
+            ami,
+            baked_image,
+            instance_type,
+            fallback_instance_types,
+            subnet_id,
+            fallback_subnet_ids,
+            security_group_ids,
+            iam_instance_profile,
+            spot: SpotOptions {
+                enabled,
+                max_price,
+                fallback_on_demand,
+            },
+        })
+    }
+}
+
+/// Spending limits read from the `[budget]` section, in the provider's currency.
+#[derive(Debug, Default)]
+pub struct BudgetConfig {
+    /// Limit on the spend of all jobs in the current profile per calendar month.
+    pub monthly: Option<f64>,
+    /// Limit on the spend of a single job.
+    pub job: Option<f64>,
+}
+
+impl BudgetConfig {
+    /// Loads the budget section from every config layer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a budget is not a number.
+    pub fn load() -> Result<Self, ConfigError> {
+        Self::from_settings(&Settings::load()?)
+    }
+
+    /// Reads the budget section from the given settings.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a budget is not a number.
+    pub fn from_settings(settings: &Settings) -> Result<Self, ConfigError> {
+        Ok(Self {
+            monthly: amount(settings, "budget.monthly")?,
+            job: amount(settings, "budget.job")?,
+        })
+    }
+
+    #[must_use]
+    pub const fn is_set(&self) -> bool {
+        self.monthly.is_some() || self.job.is_some()
+    }
+}
+
+/// Private keys tried, in order, when `ssh_key_path` is not set.
+const DEFAULT_KEY_FILES: [&str; 3] = ["id_ed25519", "id_ecdsa", "id_rsa"];
+
+/// How to reach jobs over SSH, read from `ssh_key_path` and the `[ssh]` section.
+#[derive(Debug, Clone)]
+pub struct SshConfig {
+    pub user: String,
+    pub port: u16,
+    /// Private key, or the first of `~/.ssh/id_{ed25519,ecdsa,rsa}` that exists.
+    pub key_path: String,
+    /// Passphrase of an encrypted private key; prompted for when unset.
+    pub passphrase: Option<String>,
+    /// Whether to try the identities of the agent at `SSH_AUTH_SOCK` first.
+    pub agent: bool,
+    /// Host jobs are reached through, as `[user@]host[:port]`.
+    pub jump_host: Option<String>,
+    /// Command whose standard input and output are connected to the job's SSH
+    /// server, e.g. an SSM session; `%h`, `%p` and `%i` stand for the job's
+    /// address, port and id.
+    pub proxy_command: Option<String>,
+    pub connect_timeout: Duration,
+    /// Interval between keepalive messages on idle connections.
+    pub keepalive: Duration,
+    /// How many times a refused or timed out connection is retried.
+    pub retries: u32,
+}
+
+impl SshConfig {
+    /// Loads the SSH settings from every config layer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a value is malformed or the passphrase cannot be resolved.
+    pub fn load() -> Result<Self, ConfigError> {
+        Self::from_settings(&Settings::load()?)
+    }
+
+    /// Reads the SSH settings from the given settings.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a value is malformed or the passphrase cannot be resolved.
+    pub fn from_settings(settings: &Settings) -> Result<Self, ConfigError> {
+        let user = required(settings, "ssh.user")?;
+        let port = number(settings, "ssh.port")?;
+        let key_path = settings
+            .get("ssh_key_path")
+            .filter(|path| !path.is_empty())
+            .or_else(default_key_path)
+            .unwrap_or_default();
+        let passphrase = lookup(settings, "ssh.passphrase")?;
+        let agent = boolean(settings, "ssh.agent")?;
+        let jump_host = lookup(settings, "ssh.jump_host")?.filter(|host| !host.is_empty());
+        let proxy_command =
+            lookup(settings, "ssh.proxy_command")?.filter(|command| !command.is_empty());
+        let connect_timeout = duration(settings, "ssh.connect_timeout")?;
+        let keepalive = duration(settings, "ssh.keepalive")?;
+        let retries = number(settings, "ssh.retries")?;
+
+        Ok(Self {
+            user,
+            port,
+            key_path,
+            passphrase,
+            agent,
+            jump_host,
+            proxy_command,
+            connect_timeout,
+            keepalive,
+            retries,
+        })
+    }
+
+    /// Returns whether jobs are reached through a jump host or proxy command,
+    /// and so by their private address.
+    #[must_use]
+    pub const fn is_proxied(&self) -> bool {
+        self.jump_host.is_some() || self.proxy_command.is_some()
+    }
+}
+
+fn default_key_path() -> Option<String> {
+    let home = std::env::var("HOME").ok()?;
+    DEFAULT_KEY_FILES
+        .iter()
+        .map(|file| format!("{home}/.ssh/{file}"))
+        .find(|path| Path::new(path).exists())
+}
+
+/// Object storage read from the `[storage]` section, where jobs sync their
+/// corpus, coverage reports and logs. Any S3-compatible service works: AWS S3,
+/// Hetzner Object Storage or a local MinIO.
+#[derive(Debug)]
+pub struct StorageConfig {
+    pub bucket: String,
+    /// Endpoint of a service other than AWS S3, e.g. `https://fsn1.your-objectstorage.com`.
+    pub endpoint: Option<String>,
+    pub region: String,
+    /// Key prefix under which each job gets its own directory.
+    pub prefix: String,
+    /// Static credentials; when unset, the AWS default chain (or the instance
+    /// profile on the job) is used.
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    /// How often jobs sync their artifacts.
+    pub interval: Duration,
+    /// Directories under `/root` synced besides the job logs: the corpus
+    /// directories followed by `storage.paths`.
+    pub paths: Vec<String>,
+}
+
+impl StorageConfig {
+    /// Loads the storage section from every config layer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `storage.interval` is not a duration or a credential
+    /// cannot be resolved.
+    pub fn load() -> Result<Option<Self>, ConfigError> {
+        Self::from_settings(&Settings::load()?)
+    }
+
+    /// Reads the storage section from the given settings, `None` when no
+    /// `storage.bucket` is configured.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `storage.interval` is not a duration or a credential
+    /// cannot be resolved.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, ConfigError> {
+        let Some(bucket) = lookup(settings, "storage.bucket")? else {
+            return Ok(None);
+        };
+        let endpoint = lookup(settings, "storage.endpoint")?;
+        let region = required(settings, "storage.region")?;
+        let prefix = required(settings, "storage.prefix")?;
+        let access_key_id = lookup(settings, "storage.access_key_id")?;
+        let secret_access_key = lookup(settings, "storage.secret_access_key")?;
+        let interval = duration(settings, "storage.interval")?;
+        let mut paths = list(settings, "campaign.corpus_dirs")?;
+        paths.extend(list(settings, "storage.paths")?);
+
+        Ok(Some(Self {
+            bucket,
+            endpoint,
+            region,
+            prefix,
+            access_key_id,
+            secret_access_key,
+            interval,
+            paths,
+        }))
+    }
+}
+
+/// Returns the key recording the latest image baked for a toolchain, e.g.
+/// `hetzner.images.default`.
+#[must_use]
+pub fn baked_image_key(provider: &str, toolchain: &str) -> String {
+    format!("{provider}.images.{toolchain}")
+}
+
+/// Returns the latest image baked for the configured toolchain.
+fn baked_image(settings: &Settings, provider: &str) -> Result<Option<String>, ConfigError> {
+    let toolchain = settings.get("toolchain").unwrap_or_default();
+    lookup(settings, &baked_image_key(provider, &toolchain))
+}
+
+/// Returns the effective value of `key`, resolving secret references such as
+/// `keyring:hetzner.token` transparently.
+fn lookup(settings: &Settings, key: &str) -> Result<Option<String>, ConfigError> {
+    settings
+        .get(key)
+        .map(|value| {
+            secrets::resolve(&value).map_err(|e| ConfigError::Secret {
+                key: key.to_string(),
+                reason: e.to_string(),
+            })
+        })
+        .transpose()
+}
+
+/// Returns the effective value of `key`, or an error naming the key when unset.
+fn required(settings: &Settings, key: &str) -> Result<String, ConfigError> {
+    lookup(settings, key)?.ok_or_else(|| ConfigError::MissingKey(key.to_string()))
+}
+
+/// Parses the effective value of `key` as a boolean, `false` when unset.
+fn boolean(settings: &Settings, key: &str) -> Result<bool, ConfigError> {
+    match lookup(settings, key)? {
+        None => Ok(false),
+        Some(value) => value
+            .parse::<bool>()
+            .map_err(|_| ConfigError::InvalidValue {
+                key: key.to_string(),
+                value,
+            }),
+    }
+}
+
+/// Parses the effective value of `key` as a non-negative amount.
+fn amount(settings: &Settings, key: &str) -> Result<Option<f64>, ConfigError> {
+    lookup(settings, key)?
+        .map(|value| match value.parse::<f64>() {
+            Ok(amount) if amount >= 0.0 => Ok(amount),
+            _ => Err(ConfigError::InvalidValue {
+                key: key.to_string(),
+                value,
+            }),
+        })
+        .transpose()
+}
+
+/// Parses the required value of `key` as a number.
+fn number<T: std::str::FromStr>(settings: &Settings, key: &str) -> Result<T, ConfigError> {
+    let value = required(settings, key)?;
+    value.parse::<T>().map_err(|_| ConfigError::InvalidValue {
+        key: key.to_string(),
+        value,
+    })
+}
+
+/// Parses the required value of `key` as a duration such as `30s` or `10m`.
+fn duration(settings: &Settings, key: &str) -> Result<Duration, ConfigError> {
+    let value = required(settings, key)?;
+    parse_duration(&value).map_err(|_| ConfigError::InvalidValue {
+        key: key.to_string(),
+        value,
+    })
+}
+
+/// Splits a comma-separated value into its non-empty entries.
+fn list(settings: &Settings, key: &str) -> Result<Vec<String>, ConfigError> {
+    Ok(lookup(settings, key)?
+        .map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|entry| !entry.is_empty())
+                .map(ToString::to_string)
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+/// Loads configuration using `config_path` as the project config file.
 ///
 /// # Errors
 ///
 /// This function will return an error if the config cannot be loaded.
-pub fn load_config(_config_path: &str) -> Result<Config, Box<dyn std::error::Error + Send + Sync>> {
-    Ok(Config::new())
+pub fn load_config(config_path: &str) -> Result<Config, Box<dyn std::error::Error + Send + Sync>> {
+    let settings = Settings::load_with(Some(Path::new(config_path)))?;
+    Ok(Config::from_settings(&settings))
 }
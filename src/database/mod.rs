@@ -4,6 +4,10 @@ use std::io::Write;
 
 use toml::Value;
 
+mod registry;
+
+pub use registry::{JobRecord, JobStatus, Registry};
+
 const DATABASE_DIR: &str = ".ffs";
 const DATABASE_FILENAME: &str = "database.toml";
 
@@ -60,12 +64,8 @@ impl Database {
             fs::File::create(&database_file)?;
         }
         let contents = fs::read_to_string(&database_file)?;
-        let value = contents.parse::<Value>()?;
-        let table = value.as_table().unwrap();
-        self.data = table
-            .iter()
-            .map(|(k, v)| (k.clone(), v.as_str().unwrap_or_default().to_string()))
-            .collect();
+        self.filename = database_file;
+        self.data = parse(&contents)?;
         Ok(())
     }
 
@@ -86,8 +86,79 @@ impl Database {
         Ok(())
     }
 
+    /// Removes a key from the database.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data cannot be written to the file.
+    pub fn remove(&mut self, key: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if self.data.remove(key).is_some() {
+            let toml = toml::to_string(&self.data)?;
+            fs::write(&self.filename, toml)?;
+        }
+        Ok(())
+    }
+
     #[must_use]
     pub fn get(&self, key: &str) -> Option<String> {
         self.data.get(key).cloned()
     }
+
+    /// Returns the path of the database file.
+    #[must_use]
+    pub fn path(&self) -> &str {
+        &self.filename
+    }
+
+    /// Returns every key-value pair in the database.
+    #[must_use]
+    pub const fn entries(&self) -> &HashMap<String, String> {
+        &self.data
+    }
+}
+
+/// Parses TOML contents into flat dotted keys, as stored by the database.
+///
+/// # Errors
+///
+/// Returns an error if the contents are not valid TOML.
+pub fn parse(
+    contents: &str,
+) -> Result<HashMap<String, String>, Box<dyn std::error::Error + Send + Sync>> {
+    let value = contents.parse::<Value>()?;
+    let mut data = HashMap::new();
+    if let Some(table) = value.as_table() {
+        flatten("", table, &mut data);
+    }
+    Ok(data)
+}
+
+/// Flattens nested tables such as `[hetzner]` into dotted keys (`hetzner.token`)
+/// and arrays into comma-separated values.
+fn flatten(prefix: &str, table: &toml::value::Table, data: &mut HashMap<String, String>) {
+    for (key, value) in table {
+        let key = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{prefix}.{key}")
+        };
+        match value {
+            Value::Table(nested) => flatten(&key, nested, data),
+            Value::Array(items) => {
+                let items: Vec<String> = items.iter().map(scalar).collect();
+                data.insert(key, items.join(","));
+            }
+            _ => {
+                data.insert(key, scalar(value));
+            }
+        }
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Table(_) | Value::Array(_) => String::new(),
+        other => other.to_string(),
+    }
 }
@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+use serde::{Deserialize, Serialize};
+
+use super::DATABASE_DIR;
+use crate::utils::timestamp;
+
+const REGISTRY_FILENAME: &str = "jobs.toml";
+
+/// Lifecycle state of a job recorded in the registry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Running,
+    Stopped,
+    Failed,
+    /// Reclaimed by the provider, e.g. a spot interruption; can be resumed.
+    Interrupted,
+}
+
+impl fmt::Display for JobStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Running => write!(f, "running"),
+            Self::Stopped => write!(f, "stopped"),
+            Self::Failed => write!(f, "failed"),
+            Self::Interrupted => write!(f, "interrupted"),
+        }
+    }
+}
+
+/// A job started by this machine, kept after the server is gone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub id: String,
+    pub name: String,
+    pub provider: String,
+    pub profile: String,
+    pub status: JobStatus,
+    pub created_at: u64,
+    pub terminated_at: Option<u64>,
+    pub campaign: Option<String>,
+    pub results: Option<String>,
+    pub exit_reason: Option<String>,
+    /// Unix time after which the job is torn down, from `--max-duration`.
+    pub deadline: Option<u64>,
+    /// Whether the job is torn down once its campaign finishes.
+    #[serde(default)]
+    pub stop_on_finish: bool,
+    /// Whether the job runs on spot capacity and may be interrupted.
+    #[serde(default)]
+    pub spot: bool,
+    /// Whether the job has a public IPv6 address only, from `--ipv6-only`.
+    #[serde(default)]
+    pub ipv6_only: bool,
+    /// Persistent volume attached to the job.
+    pub volume: Option<String>,
+    /// Key prefix of the job's artifacts in `storage.bucket`.
+    pub artifacts: Option<String>,
+    /// Id of the interrupted job this one resumed.
+    pub resumed_from: Option<String>,
+    /// Hourly price when the job was started, in `currency`.
+    pub hourly_price: Option<f64>,
+    pub currency: Option<String>,
+    /// Ephemeral private key ffs logs in with, dropped once the job is terminated.
+    pub ssh_key: Option<String>,
+    /// Labels given with `--label`, used to select jobs.
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl JobRecord {
+    /// Creates a running record stamped with the current time.
+    #[must_use]
+    pub fn new(id: &str, name: &str, provider: &str, profile: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            name: name.to_string(),
+            provider: provider.to_string(),
+            profile: profile.to_string(),
+            status: JobStatus::Running,
+            created_at: timestamp(),
+            terminated_at: None,
+            campaign: None,
+            results: None,
+            exit_reason: None,
+            deadline: None,
+            stop_on_finish: false,
+            spot: false,
+            ipv6_only: false,
+            volume: None,
+            artifacts: None,
+            resumed_from: None,
+            hourly_price: None,
+            currency: None,
+            ssh_key: None,
+            labels: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct RegistryFile {
+    #[serde(default)]
+    jobs: Vec<JobRecord>,
+}
+
+/// Local history of every job started with ffs, stored in `~/.ffs/jobs.toml`.
+pub struct Registry {
+    filename: String,
+    jobs: Vec<JobRecord>,
+}
+
+impl Registry {
+    /// Loads the registry from the file system, starting empty if it does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the registry file cannot be read or parsed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HOME environment variable is not set.
+    pub fn load() -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let home = std::env::var("HOME").unwrap();
+        let filename = format!("{home}/{DATABASE_DIR}/{REGISTRY_FILENAME}");
+        let jobs = match fs::read_to_string(&filename) {
+            Ok(contents) => toml::from_str::<RegistryFile>(&contents)?.jobs,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(Box::new(e)),
+        };
+        Ok(Self { filename, jobs })
+    }
+
+    /// Writes the registry back to the file system, readable only by the user
+    /// since it holds the ephemeral keys of running jobs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the registry cannot be written.
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let file = RegistryFile {
+            jobs: self.jobs.clone(),
+        };
+        fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&self.filename)?
+            .write_all(toml::to_string(&file)?.as_bytes())?;
+        fs::set_permissions(&self.filename, fs::Permissions::from_mode(0o600))?;
+        Ok(())
+    }
+
+    /// Returns every recorded job, oldest first.
+    #[must_use]
+    pub fn jobs(&self) -> &[JobRecord] {
+        &self.jobs
+    }
+
+    /// Adds a job and saves the registry.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the registry cannot be written.
+    pub fn insert(
+        &mut self,
+        record: JobRecord,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.jobs.push(record);
+        self.save()
+    }
+
+    /// Finds the most recent job whose id or name matches `query`.
+    #[must_use]
+    pub fn find(&self, query: &str) -> Option<&JobRecord> {
+        self.jobs
+            .iter()
+            .rev()
+            .find(|job| job.id == query || job.name == query)
+    }
+
+    /// Marks the job with the given id as terminated and saves the registry.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the registry cannot be written.
+    pub fn terminate(
+        &mut self,
+        id: &str,
+        status: JobStatus,
+        exit_reason: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(job) = self
+            .jobs
+            .iter_mut()
+            .rev()
+            .find(|job| job.id == id && job.terminated_at.is_none())
+        {
+            job.status = status;
+            job.terminated_at = Some(timestamp());
+            job.exit_reason = Some(exit_reason.to_string());
+            job.ssh_key = None;
+        }
+        self.save()
+    }
+}
@@ -1,6 +1,546 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::config::{BudgetConfig, SshConfig};
+use crate::database::{JobStatus, Registry};
+use crate::pricing;
+use crate::providers::{Provider, Resource, ResourceKind, JOB_DIR};
+use crate::ssh::{self, Connection};
+use crate::utils::timestamp;
+
+/// Reference to the most recently started job that is still running.
+pub const LAST_JOB: &str = "@last";
+
 #[derive(Debug)]
 pub struct Job {
     pub id: String,
-    pub ipv4: String,
+    /// Public and private addresses of the server, IPv4 and IPv6.
+    pub addresses: Vec<Address>,
     pub name: Option<String>,
+    /// Whether the job runs on spot capacity and may be interrupted.
+    pub spot: bool,
+    /// Whether the server is running, as opposed to starting or shutting down.
+    pub running: bool,
+}
+
+impl Job {
+    /// Returns the address to reach the job at over SSH, see [`reachable`].
+    #[must_use]
+    pub fn ssh_address(&self, ssh: &SshConfig) -> Option<String> {
+        reachable(&self.addresses, ssh).map(|address| address.ip.to_string())
+    }
+}
+
+/// An address of a job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Address {
+    pub ip: IpAddr,
+    /// Whether the address is reachable from the internet, as opposed to only
+    /// from the job's private network.
+    pub public: bool,
+}
+
+impl Address {
+    /// Parses a public address as reported by a provider, which leaves it empty
+    /// while none is assigned.
+    #[must_use]
+    pub fn public(ip: &str) -> Option<Self> {
+        ip.parse().ok().map(|ip| Self { ip, public: true })
+    }
+
+    /// Parses a private address as reported by a provider.
+    #[must_use]
+    pub fn private(ip: &str) -> Option<Self> {
+        ip.parse().ok().map(|ip| Self { ip, public: false })
+    }
+}
+
+/// Chooses the address to reach a job at over SSH: a private one when
+/// connecting through a jump host or proxy command, a public one otherwise, and
+/// IPv4 over IPv6 so IPv6-only jobs are reached over IPv6.
+#[must_use]
+pub fn reachable<'a>(addresses: &'a [Address], ssh: &SshConfig) -> Option<&'a Address> {
+    let proxied = ssh.is_proxied();
+    addresses
+        .iter()
+        .filter(|address| proxied || address.public)
+        .min_by_key(|address| (address.public == proxied, address.ip.is_ipv6()))
+}
+
+/// What `wait` blocks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitFor {
+    /// The server runs, answers SSH and has finished provisioning.
+    Ready,
+    /// The campaign has finished, or the job is gone.
+    Finished,
+}
+
+impl FromStr for WaitFor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ready" => Ok(Self::Ready),
+            "finished" => Ok(Self::Finished),
+            _ => Err(format!(
+                "unknown wait condition `{s}` (expected ready, finished)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for WaitFor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ready => write!(f, "ready"),
+            Self::Finished => write!(f, "finished"),
+        }
+    }
+}
+
+const WAIT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Stage a job goes through until it is ready, in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Starting,
+    Booting,
+    Provisioning,
+    Ready,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Starting => write!(f, "waiting for the server to run"),
+            Self::Booting => write!(f, "waiting for SSH"),
+            Self::Provisioning => write!(f, "waiting for provisioning to finish"),
+            Self::Ready => write!(f, "ready"),
+        }
+    }
+}
+
+/// Error returned when a job does not reach the awaited state in time.
+#[derive(Debug, PartialEq, Eq)]
+pub struct WaitTimeout {
+    pub job_id: String,
+    pub until: WaitFor,
+    pub timeout: Duration,
+}
+
+impl fmt::Display for WaitTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "job {} was not {} after {}",
+            self.job_id,
+            self.until,
+            format_elapsed(self.timeout)
+        )
+    }
+}
+
+impl std::error::Error for WaitTimeout {}
+
+/// Blocks until a job is ready or its campaign has finished, printing each
+/// stage as it is reached.
+///
+/// # Errors
+///
+/// Returns an error if the job disappears before it is ready, its state cannot
+/// be read, or `timeout` elapses first.
+pub async fn wait(
+    provider: &dyn Provider,
+    job_id: &str,
+    ssh: &SshConfig,
+    until: WaitFor,
+    timeout: Option<Duration>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let started = Instant::now();
+    let mut reported = None;
+    loop {
+        let stage = match provider.get_job(job_id).await? {
+            None if until == WaitFor::Finished => {
+                println!("[{}] job is gone", format_elapsed(started.elapsed()));
+                return Ok(());
+            }
+            None => return Err(format!("job {job_id} no longer exists").into()),
+            Some(job) if !job.running || job.ssh_address(ssh).is_none() => Stage::Starting,
+            Some(job) => {
+                let address = job.ssh_address(ssh).unwrap_or_default();
+                let ssh = ssh.clone();
+                let probe = format!(
+                    "test -f {JOB_DIR}/installed || exit 2; test -f {JOB_DIR}/status || exit 3; cat {JOB_DIR}/status"
+                );
+                match tokio::task::spawn_blocking(move || {
+                    Connection::open(&job.id, &address, &ssh)
+                        .and_then(|connection| connection.exec(&probe))
+                })
+                .await?
+                {
+                    Err(_) => Stage::Booting,
+                    Ok((2, _)) => Stage::Provisioning,
+                    Ok((0, status)) if until == WaitFor::Finished => {
+                        println!(
+                            "[{}] campaign {}",
+                            format_elapsed(started.elapsed()),
+                            status.trim()
+                        );
+                        return Ok(());
+                    }
+                    Ok(_) => Stage::Ready,
+                }
+            }
+        };
+        if reported != Some(stage) {
+            println!("[{}] {stage}", format_elapsed(started.elapsed()));
+            reported = Some(stage);
+        }
+        if stage == Stage::Ready && until == WaitFor::Ready {
+            return Ok(());
+        }
+        if let Some(timeout) = timeout {
+            if started.elapsed() >= timeout {
+                return Err(Box::new(WaitTimeout {
+                    job_id: job_id.to_string(),
+                    until,
+                    timeout,
+                }));
+            }
+        }
+        tokio::time::sleep(WAIT_INTERVAL).await;
+    }
+}
+
+fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    if secs < 60 {
+        format!("{secs}s")
+    } else {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    }
+}
+
+/// Error returned when a job reference does not identify exactly one job.
+#[derive(Debug, PartialEq, Eq)]
+pub enum JobRefError {
+    NotFound(String),
+    Ambiguous { query: String, matches: Vec<String> },
+}
+
+impl fmt::Display for JobRefError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(query) => write!(f, "no job matches `{query}`"),
+            Self::Ambiguous { query, matches } => write!(
+                f,
+                "`{query}` is ambiguous, it matches: {}",
+                matches.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for JobRefError {}
+
+/// Resolves a job name, unique id prefix or `@last` to a provider id.
+///
+/// Exact ids and names win over prefixes; names are matched against both the
+/// provider's live jobs and the jobs recorded in the local registry.
+///
+/// # Errors
+///
+/// Returns an error if the jobs cannot be listed or the reference matches no
+/// job or more than one.
+pub async fn resolve(
+    provider: &dyn Provider,
+    registry: &Registry,
+    provider_name: &str,
+    query: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let recorded = registry
+        .jobs()
+        .iter()
+        .filter(|job| job.provider == provider_name && job.terminated_at.is_none());
+
+    if query == LAST_JOB {
+        return recorded
+            .last()
+            .map(|job| job.id.clone())
+            .ok_or_else(|| JobRefError::NotFound(query.to_string()).into());
+    }
+
+    let live = provider.list_jobs().await?;
+    if live.iter().any(|job| job.id == query) {
+        return Ok(query.to_string());
+    }
+
+    let mut by_name: Vec<(String, String)> = live
+        .iter()
+        .filter(|job| job.name.as_deref() == Some(query))
+        .map(|job| (job.id.clone(), query.to_string()))
+        .chain(
+            recorded
+                .filter(|job| job.name == query)
+                .map(|job| (job.id.clone(), job.name.clone())),
+        )
+        .collect();
+    by_name.sort();
+    by_name.dedup();
+    if !by_name.is_empty() {
+        return single(query, by_name);
+    }
+
+    let by_prefix = live
+        .iter()
+        .filter(|job| job.id.starts_with(query))
+        .map(|job| (job.id.clone(), job.name.clone().unwrap_or_default()))
+        .collect();
+    single(query, by_prefix)
+}
+
+fn single(
+    query: &str,
+    mut matches: Vec<(String, String)>,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    match matches.len() {
+        0 => Err(JobRefError::NotFound(query.to_string()).into()),
+        1 => Ok(matches.remove(0).0),
+        _ => Err(JobRefError::Ambiguous {
+            query: query.to_string(),
+            matches: matches
+                .into_iter()
+                .map(|(id, name)| format!("{id} ({name})"))
+                .collect(),
+        }
+        .into()),
+    }
+}
+
+/// Returns the id and name of every running job recorded for the provider that
+/// carries all of `labels`, oldest first.
+#[must_use]
+pub fn select(
+    registry: &Registry,
+    provider_name: &str,
+    labels: &[(String, String)],
+) -> Vec<(String, String)> {
+    registry
+        .jobs()
+        .iter()
+        .filter(|job| job.provider == provider_name && job.terminated_at.is_none())
+        .filter(|job| {
+            labels
+                .iter()
+                .all(|(key, value)| job.labels.get(key) == Some(value))
+        })
+        .map(|job| (job.id.clone(), job.name.clone()))
+        .collect()
+}
+
+/// Exit status reported for a job the command could not be run on, as OpenSSH does.
+const UNREACHABLE_STATUS: i32 = 255;
+
+/// Runs `command` on every target job in parallel, printing each line of output
+/// prefixed with the job's name, and returns the highest exit status, `255` for
+/// a job that could not be reached.
+///
+/// # Errors
+///
+/// Returns an error if the jobs cannot be listed.
+pub async fn exec(
+    provider: &dyn Provider,
+    targets: Vec<(String, String)>,
+    ssh: &SshConfig,
+    command: &str,
+) -> Result<i32, Box<dyn std::error::Error + Send + Sync>> {
+    let mut live = provider.list_jobs().await?;
+    let mut status = 0;
+    let mut tasks = Vec::new();
+    for (id, name) in targets {
+        let job = live
+            .iter()
+            .position(|job| job.id == id)
+            .map(|index| live.swap_remove(index));
+        let Some(address) = job.and_then(|job| job.ssh_address(ssh)) else {
+            eprintln!("[{name}] job {id} is not reachable");
+            status = UNREACHABLE_STATUS;
+            continue;
+        };
+        let (ssh, command, prefix) = (ssh.clone(), command.to_string(), name.clone());
+        let task = tokio::task::spawn_blocking(move || {
+            Connection::open(&id, &address, &ssh)?
+                .stream_lines(&command, |line| println!("[{prefix}] {line}"))
+        });
+        tasks.push((name, task));
+    }
+    for (name, task) in tasks {
+        match task.await? {
+            Ok(0) => {}
+            Ok(code) => {
+                eprintln!("[{name}] exited with status {code}");
+                status = status.max(code);
+            }
+            Err(e) => {
+                eprintln!("[{name}] {e}");
+                status = UNREACHABLE_STATUS;
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// Stops running jobs that are past their deadline, whose campaign finished
+/// with `--stop-on-finish`, or that hit a hard budget limit, and marks spot jobs
+/// that were reclaimed as interrupted, returning the id and reason of each. Jobs stopped for budget reasons first run
+/// the upload hook so their results are collected.
+///
+/// # Errors
+///
+/// Returns an error if a job cannot be stopped or the registry cannot be written.
+pub async fn reap(
+    provider: &dyn Provider,
+    registry: &mut Registry,
+    provider_name: &str,
+    ssh: &SshConfig,
+    budget: &BudgetConfig,
+) -> Result<Vec<(String, String)>, Box<dyn std::error::Error + Send + Sync>> {
+    let now = timestamp();
+    let candidates: Vec<_> = registry
+        .jobs()
+        .iter()
+        .filter(|job| job.provider == provider_name && job.terminated_at.is_none())
+        .filter(|job| job.deadline.is_some() || job.stop_on_finish || job.spot || budget.is_set())
+        .cloned()
+        .collect();
+
+    let mut reaped = Vec::new();
+    for record in candidates {
+        let reason = if let Some(reason) = pricing::over_budget(budget, registry, &record) {
+            let job = provider.get_job(&record.id).await.ok().flatten();
+            if let Some(address) = job.and_then(|job| job.ssh_address(ssh)) {
+                let (id, ssh) = (record.id.clone(), ssh.clone());
+                let upload = format!("[ -x {JOB_DIR}/upload.sh ] && {JOB_DIR}/upload.sh");
+                let _ = tokio::task::spawn_blocking(move || {
+                    Connection::open(&id, &address, &ssh)
+                        .and_then(|connection| connection.exec(&upload))
+                })
+                .await;
+            }
+            Some((reason, true, JobStatus::Stopped))
+        } else if record.deadline.is_some_and(|deadline| deadline <= now) {
+            Some(("max duration reached".to_string(), true, JobStatus::Stopped))
+        } else if record.stop_on_finish || record.spot {
+            match provider.get_job(&record.id).await {
+                Ok(Some(job)) if job.ssh_address(ssh).is_none() && record.spot => Some((
+                    "spot instance reclaimed".to_string(),
+                    false,
+                    JobStatus::Interrupted,
+                )),
+                Ok(Some(job)) => {
+                    let address = job.ssh_address(ssh).unwrap_or_default();
+                    let ssh = ssh.clone();
+                    let status = tokio::task::spawn_blocking(move || {
+                        Connection::open(&job.id, &address, &ssh).and_then(|connection| {
+                            connection.read_file(&format!("{JOB_DIR}/status"))
+                        })
+                    })
+                    .await?;
+                    match status.ok().flatten().as_deref().map(str::trim) {
+                        Some("interrupted") => Some((
+                            "spot interruption".to_string(),
+                            true,
+                            JobStatus::Interrupted,
+                        )),
+                        Some(status) if record.stop_on_finish => {
+                            Some((format!("campaign {status}"), true, JobStatus::Stopped))
+                        }
+                        _ => None,
+                    }
+                }
+                Ok(None) if record.spot => Some((
+                    "spot instance reclaimed".to_string(),
+                    false,
+                    JobStatus::Interrupted,
+                )),
+                Ok(None) => Some((
+                    "server no longer exists".to_string(),
+                    false,
+                    JobStatus::Stopped,
+                )),
+                Err(_) => None,
+            }
+        } else {
+            None
+        };
+
+        if let Some((reason, stop, status)) = reason {
+            if stop {
+                provider.stop_job(&record.id).await?;
+            }
+            registry.terminate(&record.id, status, &reason)?;
+            ssh::forget(&record.id)?;
+            reaped.push((record.id, reason));
+        }
+    }
+
+    Ok(reaped)
+}
+
+/// Picks the ffs-tagged resources that can be garbage collected, with the reason
+/// for each: servers missing from the local registry, older than `ttl` or stuck
+/// provisioning for longer than `provisioning_timeout`; unattached volumes; keys
+/// no running job is named after; and security groups once no server remains.
+#[must_use]
+pub fn gc_candidates(
+    resources: Vec<Resource>,
+    registry: &Registry,
+    provider_name: &str,
+    ttl: Duration,
+    provisioning_timeout: Duration,
+) -> Vec<(Resource, String)> {
+    let now = timestamp();
+    let running: Vec<_> = registry
+        .jobs()
+        .iter()
+        .filter(|job| job.provider == provider_name && job.terminated_at.is_none())
+        .collect();
+    let older_than = |resource: &Resource, limit: Duration| {
+        resource
+            .created_at
+            .is_some_and(|created_at| created_at + limit.as_secs() < now)
+    };
+
+    let mut candidates = Vec::new();
+    let mut servers_left = false;
+    for resource in resources {
+        let reason = match resource.kind {
+            ResourceKind::Server => {
+                if !running.iter().any(|job| job.id == resource.id) {
+                    Some("not in the local registry".to_string())
+                } else if resource.busy && older_than(&resource, provisioning_timeout) {
+                    Some("stuck provisioning".to_string())
+                } else if older_than(&resource, ttl) {
+                    Some("older than gc.ttl".to_string())
+                } else {
+                    servers_left = true;
+                    None
+                }
+            }
+            ResourceKind::Volume => (!resource.busy).then(|| "not attached".to_string()),
+            ResourceKind::SshKey => (!running.iter().any(|job| job.name == resource.name))
+                .then(|| "no running job uses it".to_string()),
+            ResourceKind::SecurityGroup => Some("no ffs server remains".to_string()),
+        };
+        if let Some(reason) = reason {
+            candidates.push((resource, reason));
+        }
+    }
+    if servers_left {
+        candidates.retain(|(resource, _)| resource.kind != ResourceKind::SecurityGroup);
+    }
+    candidates
 }
@@ -1,5 +1,10 @@
+pub mod artifacts;
+pub mod checkpoints;
 pub mod config;
 pub mod database;
 pub mod jobs;
+pub mod pricing;
 pub mod providers;
+pub mod secrets;
+pub mod ssh;
 pub mod utils;
@@ -1,7 +1,21 @@
-use clap::{Parser, Subcommand};
-use ffs_cli::database::Database;
-use ffs_cli::providers::ProviderFactory;
-use ffs_cli::utils::timestamp;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{ArgGroup, Parser, Subcommand};
+use ffs_cli::config::{self, BudgetConfig, Config, Settings, SshConfig, StorageConfig};
+use ffs_cli::database::{Database, JobRecord, JobStatus, Registry};
+use ffs_cli::jobs::{self, WaitFor};
+use ffs_cli::pricing;
+use ffs_cli::providers::{
+    self, ArtifactSync, JobSpec, ProviderFactory, ResourceKind, VolumeSpec, CAMPAIGN_SESSION,
+    DEFAULT_TOOLCHAIN,
+};
+use ffs_cli::secrets::{self, Backend};
+use ffs_cli::ssh;
+use ffs_cli::utils::{parse_duration, timestamp};
+use ffs_cli::{artifacts, checkpoints};
 
 const DEFAULT_PROVIDER: &str = "hetzner";
 const DEFAULT_JOB_NAME_PREFIX: &str = "ffs-job-";
@@ -11,6 +25,12 @@ const DEFAULT_JOB_NAME_PREFIX: &str = "ffs-job-";
 #[command(about = "A CLI tool for managing cloud computing jobs")]
 #[command(version = "0.1.1")]
 struct Cli {
+    /// Project config file to use instead of the discovered ffs.toml
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Override a config key for this invocation (repeatable)
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    overrides: Vec<(String, String)>,
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -30,22 +50,94 @@ enum Commands {
     Start {
         /// Name of the job to start
         name: Option<String>,
+        /// Campaign command to run on the job once it is provisioned
+        #[arg(long)]
+        campaign: Option<String>,
+        /// Where the job's results are stored
+        #[arg(long)]
+        results: Option<String>,
+        /// Tear the job down after this long (e.g. 90m, 12h, 2d)
+        #[arg(long, value_parser = parse_duration)]
+        max_duration: Option<Duration>,
+        /// Tear the job down once its campaign finishes
+        #[arg(long)]
+        stop_on_finish: bool,
+        /// Run on spot capacity (AWS), falling back as configured
+        #[arg(long)]
+        spot: bool,
+        /// Maximum hourly spot price (AWS)
+        #[arg(long, requires = "spot")]
+        spot_max_price: Option<String>,
+        /// Give the job a public IPv6 address only, which is cheaper (Hetzner)
+        #[arg(long)]
+        ipv6_only: bool,
+        /// Keep the corpus on a persistent volume, created with SIZE if needed (e.g. corpus-foo:50G)
+        #[arg(long, value_name = "NAME[:SIZE]")]
+        volume: Option<VolumeSpec>,
+        /// Block until the job runs, answers SSH and is provisioned
+        #[arg(long)]
+        wait: bool,
+        /// Give up waiting after this long
+        #[arg(long, value_parser = parse_duration, default_value = "30m", requires = "wait")]
+        timeout: Duration,
+        /// Label the job, to select it later with --label (repeatable)
+        #[arg(long = "label", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+        labels: Vec<(String, String)>,
+    },
+    /// Block until a job is ready or its campaign has finished
+    Wait {
+        /// Name, ID prefix or @last of the job
+        id: String,
+        /// Condition to wait for (ready, finished)
+        #[arg(long = "for", default_value = "ready")]
+        until: WaitFor,
+        /// Give up after this long (e.g. 30m)
+        #[arg(long, value_parser = parse_duration)]
+        timeout: Option<Duration>,
+    },
+    /// Resume a job's campaign on a new machine from its last checkpoint
+    Resume {
+        /// Name or ID of the job to resume
+        job: String,
+        /// Name of the new job
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Save a job's corpus to a local checkpoint
+    Checkpoint {
+        /// Name, ID prefix or @last of the job
+        id: String,
+    },
+    /// Browse and download the artifacts a job synced to object storage
+    Artifacts {
+        #[command(subcommand)]
+        action: ArtifactsAction,
+    },
+    /// Manage machine images with a toolchain preinstalled
+    Image {
+        #[command(subcommand)]
+        action: ImageAction,
+    },
+    /// Manage persistent volumes kept across jobs
+    Volume {
+        #[command(subcommand)]
+        action: VolumeAction,
     },
     /// Stop a running job
     Stop {
-        /// ID of the job to stop
+        /// Name, ID prefix or @last of the job to stop
         id: String,
     },
     /// Tail logs from a job
     Tail {
-        /// ID of the job
+        /// Name, ID prefix or @last of the job
         id: String,
         /// Log file to tail
         filename: String,
     },
     /// Copy files from a job
     Scp {
-        /// ID of the job
+        /// Name, ID prefix or @last of the job
         id: String,
         /// Source file path
         filename: String,
@@ -54,22 +146,176 @@ enum Commands {
     },
     /// SSH into a job
     Ssh {
-        /// ID of the job
+        /// Name, ID prefix or @last of the job
+        id: String,
+        /// Run this command on the job instead of opening a shell
+        #[arg(long, short)]
+        command: Option<String>,
+        /// Extra options passed to ssh, after `--` (e.g. -- -L 8080:localhost:8080)
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Attach to the tmux session a job's campaign runs in (detach with Ctrl-b d)
+    Attach {
+        /// Name, ID prefix or @last of the job
+        id: String,
+    },
+    /// Forward local ports to ports on a job until interrupted
+    Forward {
+        /// Name, ID prefix or @last of the job
+        id: String,
+        /// Ports to forward, as LOCAL:REMOTE or LOCAL:HOST:REMOTE
+        #[arg(required = true, value_name = "LOCAL:REMOTE")]
+        forwards: Vec<ssh::Forward>,
+    },
+    /// Run a command on one or many jobs in parallel
+    #[command(group(ArgGroup::new("target").required(true).args(["id", "all", "labels"])))]
+    Exec {
+        /// Name, ID prefix or @last of the job
+        id: Option<String>,
+        /// Run on every running job
+        #[arg(long)]
+        all: bool,
+        /// Run on the running jobs with this label (repeatable)
+        #[arg(long = "label", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+        labels: Vec<(String, String)>,
+        /// Command to run, after `--`
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+    /// Stop jobs past their --max-duration or whose campaign finished
+    Reap {
+        /// Keep running, checking again at this interval (e.g. 5m)
+        #[arg(long, value_parser = parse_duration)]
+        watch: Option<Duration>,
+    },
+    /// Delete ffs resources that are orphaned, expired or stuck provisioning
+    Gc {
+        /// Only list what would be deleted
+        #[arg(long)]
+        dry_run: bool,
+        /// Delete without asking for confirmation
+        #[arg(long, short)]
+        yes: bool,
+    },
+    /// Report the cost of jobs started from this machine
+    Cost {
+        /// Only count usage within this period (e.g. 30d)
+        #[arg(long, value_parser = parse_duration)]
+        since: Option<Duration>,
+    },
+    /// Show jobs started from this machine, including terminated ones
+    History {
+        /// Name or ID of a job to show in detail
+        job: Option<String>,
+    },
+    /// Inspect the effective configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Manage secrets stored outside the database
+    Secret {
+        #[command(subcommand)]
+        action: SecretAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print every effective config value
+    Show {
+        /// Print the layer each value came from
+        #[arg(long)]
+        origin: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ArtifactsAction {
+    /// List the artifacts of a job
+    List {
+        /// Name or ID of the job, running or not
+        job: String,
+    },
+    /// Download the artifacts of a job
+    Pull {
+        /// Name or ID of the job, running or not
+        job: String,
+        /// Directory to download into (default: artifacts/<job name>)
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ImageAction {
+    /// Install a toolchain on a temporary server and snapshot it for future jobs
+    Bake {
+        /// Toolchain to install, as configured under [toolchains]
+        #[arg(long, default_value = DEFAULT_TOOLCHAIN)]
+        toolchain: String,
+    },
+    /// List baked images
+    #[command(alias = "ls")]
+    List,
+    /// Delete a baked image
+    Delete {
+        /// ID of the image
         id: String,
+        /// Delete without asking for confirmation
+        #[arg(long, short)]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum VolumeAction {
+    /// List persistent volumes
+    #[command(alias = "ls")]
+    List,
+    /// Delete a persistent volume and everything on it
+    Delete {
+        /// Name of the volume
+        name: String,
+        /// Delete without asking for confirmation
+        #[arg(long, short)]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum SecretAction {
+    /// Store a secret and reference it from the config key
+    Set {
+        /// Config key holding the secret (e.g. hetzner.token)
+        key: String,
+        /// Where to store the secret (keyring, file)
+        #[arg(long, default_value = "keyring")]
+        backend: Backend,
+    },
+    /// Remove a stored secret
+    Rm {
+        /// Config key holding the secret
+        key: String,
+        /// Where the secret is stored (keyring, file)
+        #[arg(long, default_value = "keyring")]
+        backend: Backend,
     },
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let cli = Cli::parse();
+    config::set_cli_overrides(cli.config, cli.overrides);
     let mut database = Database::new();
+    let settings = Settings::load()?;
 
-    let provider = ProviderFactory::create_provider(
-        database
-            .get("provider")
-            .unwrap_or_else(|| DEFAULT_PROVIDER.to_string())
-            .as_str(),
-    );
+    let provider_name = settings
+        .get("provider")
+        .unwrap_or_else(|| DEFAULT_PROVIDER.to_string());
+    let provider = ProviderFactory::create_provider(&provider_name);
+    let mut registry = Registry::load()?;
 
     match cli.command.unwrap_or(Commands::List) {
         Commands::Init {
@@ -81,23 +327,278 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         Commands::List => {
             if let Ok(jobs) = provider.list_jobs().await {
                 for job in jobs {
-                    println!("{job:?}");
+                    let cost = registry.find(&job.id).and_then(|record| {
+                        Some((pricing::accrued(record, 0)?, record.currency.clone()?))
+                    });
+                    match cost {
+                        Some((cost, currency)) => println!("{job:?} {cost:.2} {currency}"),
+                        None => println!("{job:?}"),
+                    }
                 }
             }
         }
-        Commands::Start { name } => {
+        Commands::Start {
+            name,
+            campaign,
+            results,
+            max_duration,
+            stop_on_finish,
+            spot,
+            spot_max_price,
+            ipv6_only,
+            volume,
+            wait,
+            timeout,
+            labels,
+        } => {
             let job_name =
                 name.unwrap_or_else(|| format!("{DEFAULT_JOB_NAME_PREFIX}{}", timestamp()));
             println!("Starting job {job_name}");
-            let job = provider.start_job(&job_name).await?;
+            let storage = StorageConfig::from_settings(&settings)?;
+            let spec = JobSpec {
+                campaign: campaign.clone(),
+                max_duration,
+                stop_on_finish,
+                spot: spot.then_some(true),
+                spot_max_price,
+                ipv6_only,
+                artifacts: storage.as_ref().map(|storage| {
+                    ArtifactSync::new(storage, &artifacts::prefix(storage, &job_name))
+                }),
+                volume: volume.map(|volume| VolumeSpec {
+                    paths: corpus_dirs(&settings),
+                    ..volume
+                }),
+                host_key: host_key(&settings)?,
+                ssh_key: providers::ephemeral_key(&Config::from_settings(&settings))?,
+                ..JobSpec::new(&job_name)
+            };
+            let price = provider.hourly_price(&spec).await.ok().flatten();
+            let profile = settings.get("profile").unwrap_or_default();
+            let budget = BudgetConfig::from_settings(&settings)?;
+            if let Some(price) = price {
+                println!("Estimated cost: {price}");
+                pricing::check_start(&budget, &registry, &profile, price, max_duration)?;
+            } else if budget.is_set() {
+                return Err("cannot enforce the budget: no price known for this server".into());
+            }
+            let job = provider.start_job(&spec).await?;
+            let record = JobRecord::new(&job.id, &job_name, &provider_name, &profile);
+            registry.insert(JobRecord {
+                campaign,
+                results,
+                deadline: max_duration.map(|duration| record.created_at + duration.as_secs()),
+                stop_on_finish,
+                spot: job.spot,
+                ipv6_only,
+                volume: spec.volume.as_ref().map(|volume| volume.name.clone()),
+                artifacts: spec.artifacts.as_ref().map(|sync| sync.prefix.clone()),
+                hourly_price: price.map(|price| price.amount),
+                currency: price.map(|price| price.currency.to_string()),
+                ssh_key: spec.ssh_key.as_ref().map(|key| key.private_key.clone()),
+                labels: labels.into_iter().collect(),
+                ..record
+            })?;
             println!("Job {job:?} started");
+            if wait {
+                let ssh = SshConfig::from_settings(&settings)?;
+                jobs::wait(
+                    provider.as_ref(),
+                    &job.id,
+                    &ssh,
+                    WaitFor::Ready,
+                    Some(timeout),
+                )
+                .await?;
+            }
+        }
+        Commands::Wait { id, until, timeout } => {
+            let id = jobs::resolve(provider.as_ref(), &registry, &provider_name, &id).await?;
+            let ssh = SshConfig::from_settings(&settings)?;
+            jobs::wait(provider.as_ref(), &id, &ssh, until, timeout).await?;
+        }
+        Commands::Resume { job, name } => {
+            let previous = recorded(&registry, &job)?;
+            let campaign = previous
+                .campaign
+                .clone()
+                .ok_or_else(|| format!("Job {} has no campaign to resume", previous.name))?;
+            let storage = StorageConfig::from_settings(&settings)?;
+            let checkpoint = checkpoints::path(&previous.id);
+            let restore_from = previous
+                .artifacts
+                .clone()
+                .filter(|_| storage.is_some() && !checkpoint.exists());
+            let restore = checkpoint.exists() || restore_from.is_some();
+            if !restore {
+                println!(
+                    "No checkpoint for job {}, starting from scratch",
+                    previous.name
+                );
+            }
+
+            let job_name =
+                name.unwrap_or_else(|| format!("{}-resumed-{}", previous.name, timestamp()));
+            println!("Resuming job {} as {job_name}", previous.name);
+            let spec = JobSpec {
+                campaign: Some(campaign),
+                stop_on_finish: previous.stop_on_finish,
+                spot: previous.spot.then_some(true),
+                ipv6_only: previous.ipv6_only,
+                restore,
+                artifacts: storage.as_ref().map(|storage| ArtifactSync {
+                    restore_from: restore_from.clone(),
+                    ..ArtifactSync::new(storage, &artifacts::prefix(storage, &job_name))
+                }),
+                volume: previous.volume.clone().map(|name| VolumeSpec {
+                    name,
+                    size_gb: None,
+                    paths: corpus_dirs(&settings),
+                }),
+                host_key: host_key(&settings)?,
+                ssh_key: providers::ephemeral_key(&Config::from_settings(&settings))?,
+                ..JobSpec::new(&job_name)
+            };
+            let price = provider.hourly_price(&spec).await.ok().flatten();
+            let job = provider.start_job(&spec).await?;
+            let profile = settings.get("profile").unwrap_or_default();
+            registry.insert(JobRecord {
+                campaign: spec.campaign.clone(),
+                results: previous.results.clone(),
+                stop_on_finish: previous.stop_on_finish,
+                spot: job.spot,
+                ipv6_only: previous.ipv6_only,
+                volume: previous.volume.clone(),
+                artifacts: spec.artifacts.as_ref().map(|sync| sync.prefix.clone()),
+                resumed_from: Some(previous.id.clone()),
+                hourly_price: price.map(|price| price.amount),
+                currency: price.map(|price| price.currency.to_string()),
+                ssh_key: spec.ssh_key.as_ref().map(|key| key.private_key.clone()),
+                labels: previous.labels.clone(),
+                ..JobRecord::new(&job.id, &job_name, &provider_name, &profile)
+            })?;
+            if let Some(prefix) = &restore_from {
+                println!("Restoring the artifacts synced to {prefix}");
+            } else if restore {
+                println!("Restoring checkpoint {}", checkpoint.display());
+                let ssh = SshConfig::from_settings(&settings)?;
+                let address = job
+                    .ssh_address(&ssh)
+                    .ok_or_else(|| format!("Job {} has no address yet", job.id))?;
+                checkpoints::restore(&previous.id, &job.id, &address, &ssh).await?;
+            }
+            println!("Job {job:?} started");
+        }
+        Commands::Checkpoint { id } => {
+            let id = jobs::resolve(provider.as_ref(), &registry, &provider_name, &id).await?;
+            let job = provider
+                .get_job(&id)
+                .await?
+                .ok_or_else(|| format!("Job {id} not found"))?;
+            let ssh = SshConfig::from_settings(&settings)?;
+            let address = job
+                .ssh_address(&ssh)
+                .ok_or_else(|| format!("Job {id} has no address yet"))?;
+            let path = checkpoints::save(&id, &address, &ssh, &corpus_dirs(&settings)).await?;
+            println!("Checkpoint of job {id} saved to {}", path.display());
+        }
+        Commands::Artifacts { action } => {
+            let storage = StorageConfig::from_settings(&settings)?
+                .ok_or("no object storage configured: set storage.bucket")?;
+            let (ArtifactsAction::List { job } | ArtifactsAction::Pull { job, .. }) = &action;
+            let record = recorded(&registry, job)?;
+            let prefix = record
+                .artifacts
+                .ok_or_else(|| format!("Job {} did not sync any artifacts", record.name))?;
+            match action {
+                ArtifactsAction::List { .. } => {
+                    for artifact in artifacts::list(&storage, &prefix).await? {
+                        println!("{}\t{}", artifact.size, artifact.key);
+                    }
+                }
+                ArtifactsAction::Pull { output, .. } => {
+                    let output =
+                        output.unwrap_or_else(|| PathBuf::from("artifacts").join(&record.name));
+                    let count = artifacts::pull(&storage, &prefix, &output).await?;
+                    println!("Downloaded {count} artifacts to {}", output.display());
+                }
+            }
         }
+        Commands::Image { action } => match action {
+            ImageAction::Bake { toolchain } => {
+                let script = providers::install_script(&settings, &toolchain)?;
+                println!("Baking an image for toolchain {toolchain}, this can take a while");
+                let image = provider.bake_image(&toolchain, &script).await?;
+                database.set(
+                    &config::baked_image_key(&provider_name, &toolchain),
+                    &image.id,
+                )?;
+                println!(
+                    "Image {} baked, new jobs for {toolchain} will use it",
+                    image.id
+                );
+            }
+            ImageAction::List => {
+                for image in provider.list_images().await? {
+                    let key = config::baked_image_key(&provider_name, &image.toolchain);
+                    let latest = settings.get(&key).as_deref() == Some(image.id.as_str());
+                    println!(
+                        "{}\t{}\t{}\t{}{}",
+                        image.id,
+                        image.toolchain,
+                        image.name,
+                        image.created_at.unwrap_or_default(),
+                        if latest { "\tlatest" } else { "" }
+                    );
+                }
+            }
+            ImageAction::Delete { id, yes } => {
+                if yes || confirm(&format!("Delete image {id}?"))? {
+                    provider.delete_image(&id).await?;
+                    let recorded: Vec<String> = database
+                        .entries()
+                        .iter()
+                        .filter(|(key, value)| {
+                            key.starts_with(&format!("{provider_name}.images.")) && **value == id
+                        })
+                        .map(|(key, _)| key.clone())
+                        .collect();
+                    for key in recorded {
+                        database.remove(&key)?;
+                    }
+                    println!("Image {id} deleted");
+                }
+            }
+        },
+        Commands::Volume { action } => match action {
+            VolumeAction::List => {
+                for volume in provider.list_volumes().await? {
+                    println!(
+                        "{}\t{}G\t{}\t{}",
+                        volume.name,
+                        volume.size_gb,
+                        volume.location,
+                        volume.attached_to.as_deref().unwrap_or("-")
+                    );
+                }
+            }
+            VolumeAction::Delete { name, yes } => {
+                if yes || confirm(&format!("Delete volume {name} and everything on it?"))? {
+                    provider.delete_volume(&name).await?;
+                    println!("Volume {name} deleted");
+                }
+            }
+        },
         Commands::Stop { id } => {
+            let id = jobs::resolve(provider.as_ref(), &registry, &provider_name, &id).await?;
             println!("Stopping job {id}");
             let job = provider.stop_job(&id).await?;
+            registry.terminate(&id, JobStatus::Stopped, "stopped by user")?;
+            ssh::forget(&id)?;
             println!("Job {job:?} stopped");
         }
         Commands::Tail { id, filename } => {
+            let id = jobs::resolve(provider.as_ref(), &registry, &provider_name, &id).await?;
             println!("Fetching logs for job {id} at {filename}");
             provider.tail(&id, &filename).await?;
         }
@@ -106,19 +607,288 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
             filename,
             destination,
         } => {
+            let id = jobs::resolve(provider.as_ref(), &registry, &provider_name, &id).await?;
             println!("Copying {filename} to {destination} for job {id}");
             provider.scp(&id, &filename, &destination).await?;
         }
-        Commands::Ssh { id } => match provider.get_job(&id).await? {
-            Some(job) => {
-                println!("Connecting to {}", job.ipv4);
-                std::process::Command::new("ssh")
-                    .arg(format!("root@{}", job.ipv4))
-                    .status()?;
+        Commands::Ssh { id, command, args } => {
+            let id = jobs::resolve(provider.as_ref(), &registry, &provider_name, &id).await?;
+            let ssh_config = SshConfig::from_settings(&settings)?;
+            match provider.get_job(&id).await? {
+                Some(job) => {
+                    let address = job
+                        .ssh_address(&ssh_config)
+                        .ok_or_else(|| format!("Job {id} has no address yet"))?;
+                    if command.is_none() {
+                        println!("Connecting to {address}");
+                    }
+                    let status = ssh::openssh_command(&job.id, &ssh_config)?
+                        .args(&args)
+                        .arg(address)
+                        .args(command)
+                        .status()?;
+                    if !status.success() {
+                        std::process::exit(status.code().unwrap_or(255));
+                    }
+                }
+                None => println!("Job {id} not found"),
+            }
+        }
+        Commands::Attach { id } => {
+            let id = jobs::resolve(provider.as_ref(), &registry, &provider_name, &id).await?;
+            let job = provider
+                .get_job(&id)
+                .await?
+                .ok_or_else(|| format!("Job {id} not found"))?;
+            let ssh_config = SshConfig::from_settings(&settings)?;
+            let address = job
+                .ssh_address(&ssh_config)
+                .ok_or_else(|| format!("Job {id} has no address yet"))?;
+            // The session belongs to root, which started it from user data.
+            let sudo = if ssh_config.user == "root" {
+                ""
+            } else {
+                "sudo "
+            };
+            let status = ssh::openssh_command(&job.id, &ssh_config)?
+                .arg("-t")
+                .arg(address)
+                .arg(format!("{sudo}tmux attach -t {CAMPAIGN_SESSION}"))
+                .status()?;
+            if !status.success() {
+                return Err(format!("Job {id} has no campaign session to attach to").into());
+            }
+        }
+        Commands::Forward { id, forwards } => {
+            let id = jobs::resolve(provider.as_ref(), &registry, &provider_name, &id).await?;
+            let job = provider
+                .get_job(&id)
+                .await?
+                .ok_or_else(|| format!("Job {id} not found"))?;
+            let ssh_config = SshConfig::from_settings(&settings)?;
+            let address = job
+                .ssh_address(&ssh_config)
+                .ok_or_else(|| format!("Job {id} has no address yet"))?;
+            let mut tunnels = Vec::new();
+            for forward in forwards {
+                println!("Forwarding {forward} on job {id}");
+                let (job_id, ip, ssh_config) =
+                    (job.id.clone(), address.clone(), ssh_config.clone());
+                tunnels.push(tokio::task::spawn_blocking(move || {
+                    ssh::forward(&job_id, &ip, &ssh_config, &forward)
+                }));
+            }
+            for tunnel in tunnels {
+                tunnel.await??;
+            }
+        }
+        Commands::Exec {
+            id,
+            all: _,
+            labels,
+            command,
+        } => {
+            let targets = match id {
+                Some(id) => {
+                    let id =
+                        jobs::resolve(provider.as_ref(), &registry, &provider_name, &id).await?;
+                    let name = registry
+                        .find(&id)
+                        .map_or_else(|| id.clone(), |record| record.name.clone());
+                    vec![(id, name)]
+                }
+                None => jobs::select(&registry, &provider_name, &labels),
+            };
+            if targets.is_empty() {
+                return Err("no running job matches".into());
+            }
+            let ssh = SshConfig::from_settings(&settings)?;
+            let status = jobs::exec(provider.as_ref(), targets, &ssh, &command.join(" ")).await?;
+            if status != 0 {
+                std::process::exit(status);
+            }
+        }
+        Commands::Reap { watch } => {
+            let ssh = SshConfig::from_settings(&settings)?;
+            let budget = BudgetConfig::from_settings(&settings)?;
+            loop {
+                let reaped = jobs::reap(
+                    provider.as_ref(),
+                    &mut registry,
+                    &provider_name,
+                    &ssh,
+                    &budget,
+                )
+                .await?;
+                for (id, reason) in reaped {
+                    println!("Job {id} stopped: {reason}");
+                }
+                let Some(interval) = watch else { break };
+                tokio::time::sleep(interval).await;
+            }
+        }
+        Commands::Gc { dry_run, yes } => {
+            let ttl = parse_duration(&settings.get("gc.ttl").unwrap_or_default())?;
+            let provisioning_timeout =
+                parse_duration(&settings.get("gc.provisioning_timeout").unwrap_or_default())?;
+            let resources = provider.list_resources().await?;
+            let candidates = jobs::gc_candidates(
+                resources,
+                &registry,
+                &provider_name,
+                ttl,
+                provisioning_timeout,
+            );
+            if candidates.is_empty() {
+                println!("Nothing to collect");
+                return Ok(());
+            }
+            for (resource, reason) in &candidates {
+                println!(
+                    "{} {} ({}): {reason}",
+                    resource.kind, resource.id, resource.name
+                );
+            }
+            if dry_run || !(yes || confirm(&format!("Delete {} resources?", candidates.len()))?) {
+                return Ok(());
+            }
+            for (resource, _) in &candidates {
+                match provider.delete_resource(resource).await {
+                    Ok(()) => {
+                        if resource.kind == ResourceKind::Server {
+                            registry.terminate(
+                                &resource.id,
+                                JobStatus::Stopped,
+                                "garbage collected",
+                            )?;
+                            ssh::forget(&resource.id)?;
+                        }
+                        println!("Deleted {} {}", resource.kind, resource.id);
+                    }
+                    Err(e) => println!("Failed to delete {} {}: {e}", resource.kind, resource.id),
+                }
+            }
+        }
+        Commands::Cost { since } => {
+            let since = since.map_or(0, |since| timestamp().saturating_sub(since.as_secs()));
+            let mut totals: BTreeMap<String, f64> = BTreeMap::new();
+            for record in registry.jobs() {
+                let ended_before = record.terminated_at.is_some_and(|end| end < since);
+                let (Some(cost), Some(currency)) =
+                    (pricing::accrued(record, since), &record.currency)
+                else {
+                    continue;
+                };
+                if ended_before {
+                    continue;
+                }
+                println!("{}\t{}\t{cost:.2} {currency}", record.id, record.name);
+                *totals.entry(currency.clone()).or_default() += cost;
+            }
+            for (currency, total) in totals {
+                println!("Total: {total:.2} {currency}");
+            }
+        }
+        Commands::History { job: None } => {
+            for job in registry.jobs() {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}",
+                    job.id, job.name, job.provider, job.status, job.created_at
+                );
+            }
+        }
+        Commands::History { job: Some(query) } => match registry.find(&query) {
+            Some(job) => println!("{job:#?}"),
+            None => println!("Job {query} not found in history"),
+        },
+        Commands::Config {
+            action: ConfigAction::Show { origin },
+        } => {
+            for (key, (value, source)) in settings.entries() {
+                let value = if is_sensitive(key) && !secrets::is_reference(value) {
+                    "********"
+                } else {
+                    value
+                };
+                if origin {
+                    println!("{key} = {value:?}  # {source}");
+                } else {
+                    println!("{key} = {value:?}");
+                }
+            }
+        }
+        Commands::Secret { action } => match action {
+            SecretAction::Set { key, backend } => {
+                let value = rpassword::prompt_password(format!("Value for {key}: "))?;
+                secrets::store(backend, &key, &value)?;
+                database.set(&key, &backend.reference(&key))?;
+                println!("Secret {key} stored in {}", backend.scheme());
+            }
+            SecretAction::Rm { key, backend } => {
+                secrets::remove(backend, &key)?;
+                println!("Secret {key} removed from {}", backend.scheme());
             }
-            None => println!("Job {id} not found"),
         },
     }
 
     Ok(())
 }
+
+/// Returns the corpus directories of the campaign, relative to `/root`.
+fn corpus_dirs(settings: &Settings) -> Vec<String> {
+    settings
+        .get("campaign.corpus_dirs")
+        .unwrap_or_default()
+        .split(',')
+        .map(|dir| dir.trim().to_string())
+        .filter(|dir| !dir.is_empty())
+        .collect()
+}
+
+/// Generates the host key to pin for a new job, unless the profile's user data
+/// cannot install it, in which case the first key the job presents is pinned.
+fn host_key(settings: &Settings) -> Result<Option<ssh::KeyPair>, ssh::SshError> {
+    if providers::is_script(&Config::from_settings(settings).user_data) {
+        ssh::KeyPair::generate("ffs").map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+/// Finds a job in the history by name, id or `@last`, whether or not it still runs.
+fn recorded(registry: &Registry, query: &str) -> Result<JobRecord, String> {
+    if query == jobs::LAST_JOB {
+        registry.jobs().last()
+    } else {
+        registry.find(query)
+    }
+    .cloned()
+    .ok_or_else(|| format!("Job {query} not found in history"))
+}
+
+fn confirm(prompt: &str) -> Result<bool, std::io::Error> {
+    print!("{prompt} [y/N] ");
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got `{s}`"))
+}
+
+/// Returns whether a config value should be masked when printed.
+fn is_sensitive(key: &str) -> bool {
+    [
+        "token",
+        "password",
+        "passphrase",
+        "secret",
+        "secret_access_key",
+    ]
+    .iter()
+    .any(|suffix| key.ends_with(suffix))
+}
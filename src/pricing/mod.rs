@@ -0,0 +1,186 @@
+use std::fmt;
+use std::time::Duration;
+
+use chrono::{Datelike, TimeZone, Utc};
+
+use crate::config::BudgetConfig;
+use crate::database::{JobRecord, Registry};
+use crate::utils::timestamp;
+
+/// On-demand Linux prices in USD per hour for common EC2 instance types in us-east-1.
+const EC2_ON_DEMAND: &[(&str, f64)] = &[
+    ("t3.large", 0.0832),
+    ("t3.xlarge", 0.1664),
+    ("t3.2xlarge", 0.3328),
+    ("c5.2xlarge", 0.34),
+    ("c5.4xlarge", 0.68),
+    ("c5.9xlarge", 1.53),
+    ("c6i.xlarge", 0.17),
+    ("c6i.2xlarge", 0.34),
+    ("c6i.4xlarge", 0.68),
+    ("c6i.8xlarge", 1.36),
+    ("c6i.16xlarge", 2.72),
+    ("c7i.2xlarge", 0.357),
+    ("c7i.4xlarge", 0.714),
+    ("c7i.8xlarge", 1.428),
+    ("m6i.2xlarge", 0.384),
+    ("m6i.4xlarge", 0.768),
+    ("m6i.8xlarge", 1.536),
+];
+
+/// Hourly price of a server.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HourlyPrice {
+    pub amount: f64,
+    pub currency: &'static str,
+}
+
+impl HourlyPrice {
+    #[must_use]
+    pub const fn eur(amount: f64) -> Self {
+        Self {
+            amount,
+            currency: "EUR",
+        }
+    }
+
+    #[must_use]
+    pub const fn usd(amount: f64) -> Self {
+        Self {
+            amount,
+            currency: "USD",
+        }
+    }
+}
+
+impl fmt::Display for HourlyPrice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.4} {}/h", self.amount, self.currency)
+    }
+}
+
+/// Returns the on-demand price of an EC2 instance type, if it is in the table.
+#[must_use]
+pub fn ec2_on_demand(instance_type: &str) -> Option<HourlyPrice> {
+    EC2_ON_DEMAND
+        .iter()
+        .find(|(name, _)| *name == instance_type)
+        .map(|(_, amount)| HourlyPrice::usd(*amount))
+}
+
+/// Returns the cost a job accrued between `since` and its termination (or now).
+#[must_use]
+pub fn accrued(record: &JobRecord, since: u64) -> Option<f64> {
+    let hourly = record.hourly_price?;
+    let start = record.created_at.max(since);
+    let end = record.terminated_at.unwrap_or_else(timestamp);
+    #[allow(clippy::cast_precision_loss)]
+    let hours = end.saturating_sub(start) as f64 / 3600.0;
+    Some(hourly * hours)
+}
+
+/// Returns the Unix time at which the current calendar month (UTC) started.
+#[must_use]
+pub fn month_start() -> u64 {
+    let now = Utc::now();
+    Utc.with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0)
+        .single()
+        .and_then(|start| u64::try_from(start.timestamp()).ok())
+        .unwrap_or_default()
+}
+
+/// Returns what the jobs of `profile` have cost since the start of the month.
+#[must_use]
+pub fn spent_this_month(registry: &Registry, profile: &str) -> f64 {
+    let since = month_start();
+    registry
+        .jobs()
+        .iter()
+        .filter(|job| job.profile == profile)
+        .filter(|job| job.terminated_at.is_none_or(|end| end >= since))
+        .filter_map(|job| accrued(job, since))
+        .sum()
+}
+
+/// Error returned when starting a job would exceed a budget.
+#[derive(Debug, PartialEq)]
+pub enum BudgetError {
+    Job { projected: f64, limit: f64 },
+    Monthly { projected: f64, remaining: f64 },
+}
+
+impl fmt::Display for BudgetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Job { projected, limit } => write!(
+                f,
+                "projected job cost {projected:.2} exceeds budget.job of {limit:.2}"
+            ),
+            Self::Monthly {
+                projected,
+                remaining,
+            } => write!(
+                f,
+                "projected job cost {projected:.2} exceeds the {remaining:.2} left in budget.monthly"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BudgetError {}
+
+/// Checks that a job at `price` fits the budgets. Without a `max_duration` the
+/// job is projected to run for at least an hour, and is later capped by
+/// [`over_budget`].
+///
+/// # Errors
+///
+/// Returns an error if the projected cost exceeds the job budget or what is
+/// left of the monthly budget.
+pub fn check_start(
+    budget: &BudgetConfig,
+    registry: &Registry,
+    profile: &str,
+    price: HourlyPrice,
+    max_duration: Option<Duration>,
+) -> Result<(), BudgetError> {
+    #[allow(clippy::cast_precision_loss)]
+    let hours = max_duration.map_or(1.0, |duration| duration.as_secs() as f64 / 3600.0);
+    let projected = price.amount * hours;
+    if let Some(limit) = budget.job {
+        if projected > limit {
+            return Err(BudgetError::Job { projected, limit });
+        }
+    }
+    if let Some(monthly) = budget.monthly {
+        let remaining = monthly - spent_this_month(registry, profile);
+        if projected > remaining {
+            return Err(BudgetError::Monthly {
+                projected,
+                remaining,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Returns why a running job must be stopped because a hard budget limit was
+/// reached, if it was.
+#[must_use]
+pub fn over_budget(
+    budget: &BudgetConfig,
+    registry: &Registry,
+    record: &JobRecord,
+) -> Option<String> {
+    let cost = accrued(record, 0)?;
+    if budget.job.is_some_and(|limit| cost >= limit) {
+        return Some("budget.job reached".to_string());
+    }
+    if budget
+        .monthly
+        .is_some_and(|limit| spent_this_month(registry, &record.profile) >= limit)
+    {
+        return Some("budget.monthly reached".to_string());
+    }
+    None
+}
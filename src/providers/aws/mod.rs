@@ -1,18 +1,33 @@
-use std::io::prelude::*;
-use std::net::TcpStream;
-use std::path::Path;
+use std::time::Duration;
 
 use async_trait::async_trait;
 use aws_config::meta::region::RegionProviderChain;
 use aws_sdk_ec2::client::Waiters;
 use aws_sdk_ec2::config::Region;
-use aws_sdk_ec2::types::{InstanceType, ResourceType, Tag, TagSpecification};
+use aws_sdk_ec2::error::ProvideErrorMetadata;
+use aws_sdk_ec2::operation::run_instances::builders::RunInstancesFluentBuilder;
+use aws_sdk_ec2::primitives::Blob;
+use aws_sdk_ec2::types::{
+    Filter, IamInstanceProfileSpecification, Instance, InstanceLifecycleType,
+    InstanceMarketOptionsRequest, InstanceStateName, InstanceType, MarketType, Placement,
+    ResourceType, ShutdownBehavior, SpotMarketOptions, Tag, TagSpecification, VolumeState,
+    VolumeType,
+};
 use aws_sdk_ec2::Client;
-use ssh2::Session;
+use base64::Engine;
 
-use super::Provider;
-use crate::config::Config;
-use crate::jobs::Job;
+use super::{
+    Image, JobSpec, Provider, Resource, ResourceKind, Volume, VolumeSpec, MANAGED_BY_KEY,
+    MANAGED_BY_VALUE, TOOLCHAIN_KEY, VOLUME_KEY,
+};
+use crate::config::{AwsConfig, Config, SshConfig};
+use crate::jobs::{self, Address, Job};
+use crate::pricing::{self, HourlyPrice};
+use crate::ssh;
+use crate::utils::timestamp;
+
+/// AMIs of a full toolchain can take a while to be created.
+const IMAGE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
 
 #[derive(Clone)]
 pub struct AWSProvider {}
@@ -32,30 +47,140 @@ impl AWSProvider {
 
 #[async_trait]
 impl Provider for AWSProvider {
-    async fn start_job(&self, name: &str) -> Result<Job, Box<dyn std::error::Error + Send + Sync>> {
-        let cfg = config();
-
-        let region_provider =
-            RegionProviderChain::first_try(Some(Region::new(cfg.location.clone())))
-                .or_default_provider();
-        let shared_config = aws_config::from_env().region(region_provider).load().await;
-        let client = Client::new(&shared_config);
-
-        let tag_spec = TagSpecification::builder()
-            .resource_type(ResourceType::Instance)
-            .tags(Tag::builder().key("Name").value(name).build())
-            .build();
-
-        let run_out = client
-            .run_instances()
-            .image_id(cfg.image.clone())
-            .instance_type(InstanceType::from(cfg.server_type.as_str()))
-            .min_count(1)
-            .max_count(1)
-            .key_name(cfg.ssh_key_name.clone())
-            .tag_specifications(tag_spec)
-            .send()
-            .await?;
+    async fn start_job(
+        &self,
+        spec: &JobSpec,
+    ) -> Result<Job, Box<dyn std::error::Error + Send + Sync>> {
+        if spec.ipv6_only {
+            return Err("IPv6-only jobs are only supported on Hetzner".into());
+        }
+        let name = spec.name.as_str();
+        let cfg = Config::new();
+        let aws = AwsConfig::load()?;
+        let client = client(&aws).await;
+
+        let spot = spec.spot.unwrap_or(aws.spot.enabled);
+        let max_price = spec
+            .spot_max_price
+            .clone()
+            .or_else(|| aws.spot.max_price.clone());
+        let volume = match &spec.volume {
+            Some(volume) => Some(ensure_volume(&client, &aws, volume).await?),
+            None => None,
+        };
+        // Nitro instances expose EBS volumes as NVMe devices named after the volume.
+        let volume_devices: Vec<String> = volume
+            .iter()
+            .flat_map(|(id, _)| {
+                [
+                    format!(
+                        "/dev/disk/by-id/nvme-Amazon_Elastic_Block_Store_{}",
+                        id.replace('-', "")
+                    ),
+                    "/dev/xvdf".to_string(),
+                ]
+            })
+            .collect();
+        let user_data = spec
+            .user_data(&cfg.user_data, spot, &volume_devices)?
+            .map(|user_data| base64::engine::general_purpose::STANDARD.encode(user_data));
+
+        // Try every instance type and subnet on the spot market first, then on
+        // demand if allowed, moving on only when AWS reports a capacity problem.
+        let instance_types: Vec<&str> = std::iter::once(aws.instance_type.as_str())
+            .chain(aws.fallback_instance_types.iter().map(String::as_str))
+            .collect();
+        let mut subnets: Vec<Option<&str>> = std::iter::once(aws.subnet_id.as_deref())
+            .chain(
+                aws.fallback_subnet_ids
+                    .iter()
+                    .map(|subnet| Some(subnet.as_str())),
+            )
+            .collect();
+        // A volume can only be attached in its own availability zone.
+        if let Some((_, zone)) = &volume {
+            let mut in_zone = Vec::new();
+            for subnet in subnets {
+                match subnet {
+                    Some(subnet) if subnet_zone(&client, subnet).await? != *zone => {}
+                    _ => in_zone.push(subnet),
+                }
+            }
+            if in_zone.is_empty() {
+                return Err(
+                    format!("no configured subnet is in {zone}, where the volume is").into(),
+                );
+            }
+            subnets = in_zone;
+        }
+        let mut markets = Vec::new();
+        if spot {
+            markets.push(Some(max_price));
+        }
+        if !spot || aws.spot.fallback_on_demand {
+            markets.push(None);
+        }
+
+        if let Some(key) = &spec.ssh_key {
+            import_key_pair(&client, name, &key.public_key).await?;
+        }
+
+        let mut last_error = None;
+        let mut launched = None;
+        'attempts: for market in &markets {
+            for instance_type in &instance_types {
+                for subnet in &subnets {
+                    let mut request = run_instances(&client, &aws, &cfg, spec)
+                        .instance_type(InstanceType::from(*instance_type))
+                        .set_subnet_id(subnet.map(ToString::to_string))
+                        .set_user_data(user_data.clone());
+                    if let Some(image) = &aws.baked_image {
+                        request = request.image_id(image);
+                    }
+                    if let (None, Some((_, zone))) = (subnet, &volume) {
+                        request =
+                            request.placement(Placement::builder().availability_zone(zone).build());
+                    }
+                    if let Some(max_price) = market {
+                        request = request.instance_market_options(
+                            InstanceMarketOptionsRequest::builder()
+                                .market_type(MarketType::Spot)
+                                .spot_options(
+                                    SpotMarketOptions::builder()
+                                        .set_max_price(max_price.clone())
+                                        .build(),
+                                )
+                                .build(),
+                        );
+                    }
+                    match request.send().await {
+                        Ok(output) => {
+                            launched = Some(output);
+                            break 'attempts;
+                        }
+                        Err(e) if is_capacity_error(e.code()) => {
+                            println!(
+                                "No {} capacity for {instance_type}{}: {}",
+                                if market.is_some() {
+                                    "spot"
+                                } else {
+                                    "on-demand"
+                                },
+                                subnet.map(|s| format!(" in {s}")).unwrap_or_default(),
+                                e.code().unwrap_or_default()
+                            );
+                            last_error = Some(e);
+                        }
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+            }
+        }
+        let run_out = match (launched, last_error) {
+            (Some(output), _) => output,
+            (None, Some(e)) => return Err(e.into()),
+            (None, None) => return Err("no instance created".into()),
+        };
 
         let instance = run_out.instances().first().ok_or("no instance created")?;
 
@@ -64,12 +189,22 @@ impl Provider for AWSProvider {
             .ok_or("missing instance id")?
             .to_string();
 
-        // Wait until instance is running
-        let _ = client
+        client
             .wait_until_instance_running()
             .instance_ids(instance_id.clone())
-            .wait(std::time::Duration::from_secs(120))
-            .await;
+            .wait(Duration::from_secs(300))
+            .await
+            .map_err(|e| format!("instance {instance_id} did not start running: {e}"))?;
+
+        if let Some((volume_id, _)) = &volume {
+            client
+                .attach_volume()
+                .volume_id(volume_id)
+                .instance_id(&instance_id)
+                .device("/dev/sdf")
+                .send()
+                .await?;
+        }
 
         let desc = client
             .describe_instances()
@@ -77,25 +212,23 @@ impl Provider for AWSProvider {
             .send()
             .await?;
 
-        let ipv4 = desc
+        let described = desc
             .reservations()
             .first()
-            .and_then(|res| res.instances().first())
-            .and_then(|inst| inst.public_ip_address())
-            .unwrap_or("")
-            .to_string();
+            .and_then(|res| res.instances().first());
 
         let job = Job {
             id: instance_id.clone(),
-            ipv4: ipv4.clone(),
+            addresses: described.map(addresses).unwrap_or_default(),
             name: Some(name.to_string()),
+            spot: is_spot(instance),
+            running: true,
         };
 
-        let key_path = cfg.ssh_key_path.clone();
-        tokio::spawn(async move {
-            let _ = tokio::task::spawn_blocking(move || super::install_over_ssh(&ipv4, &key_path))
-                .await;
-        });
+        super::remember_keys(&job.id, spec)?;
+        if aws.baked_image.is_none() {
+            super::spawn_install(&job)?;
+        }
 
         Ok(job)
     }
@@ -104,12 +237,7 @@ impl Provider for AWSProvider {
         &self,
         job_id: &str,
     ) -> Result<Option<Job>, Box<dyn std::error::Error + Send + Sync>> {
-        let cfg = config();
-        let region_provider =
-            RegionProviderChain::first_try(Some(Region::new(cfg.location.clone())))
-                .or_default_provider();
-        let shared_config = aws_config::from_env().region(region_provider).load().await;
-        let client = Client::new(&shared_config);
+        let client = client(&AwsConfig::load()?).await;
 
         let desc = client
             .describe_instances()
@@ -119,17 +247,12 @@ impl Provider for AWSProvider {
 
         if let Some(reservation) = desc.reservations().first() {
             if let Some(instance) = reservation.instances().first() {
-                let name_tag = instance
-                    .tags()
-                    .iter()
-                    .find(|t| t.key() == Some("Name"))
-                    .and_then(|t| t.value())
-                    .map(ToString::to_string);
-
                 return Ok(Some(Job {
                     id: instance.instance_id().unwrap_or_default().to_string(),
-                    ipv4: instance.public_ip_address().unwrap_or_default().to_string(),
-                    name: name_tag,
+                    addresses: addresses(instance),
+                    name: name_tag(instance.tags()),
+                    spot: is_spot(instance),
+                    running: is_running(instance),
                 }));
             }
         }
@@ -141,50 +264,51 @@ impl Provider for AWSProvider {
         &self,
         job_id: &str,
     ) -> Result<Job, Box<dyn std::error::Error + Send + Sync>> {
-        let cfg = config();
-        let region_provider =
-            RegionProviderChain::first_try(Some(Region::new(cfg.location.clone())))
-                .or_default_provider();
-        let shared_config = aws_config::from_env().region(region_provider).load().await;
-        let client = Client::new(&shared_config);
+        let client = client(&AwsConfig::load()?).await;
 
+        let desc = client
+            .describe_instances()
+            .instance_ids(job_id)
+            .send()
+            .await?;
+        let key_name = desc
+            .reservations()
+            .first()
+            .and_then(|res| res.instances().first())
+            .and_then(Instance::key_name)
+            .map(ToString::to_string);
         client
             .terminate_instances()
             .instance_ids(job_id)
             .send()
             .await?;
+        if let Some(key_name) = key_name {
+            delete_key_pair(&client, &key_name).await?;
+        }
 
         Ok(Job {
             id: job_id.to_string(),
-            ipv4: String::new(),
+            addresses: Vec::new(),
             name: None,
+            spot: false,
+            running: false,
         })
     }
 
     async fn list_jobs(&self) -> Result<Vec<Job>, Box<dyn std::error::Error + Send + Sync>> {
-        let cfg = config();
-        let region_provider =
-            RegionProviderChain::first_try(Some(Region::new(cfg.location.clone())))
-                .or_default_provider();
-        let shared_config = aws_config::from_env().region(region_provider).load().await;
-        let client = Client::new(&shared_config);
+        let client = client(&AwsConfig::load()?).await;
 
         let desc = client.describe_instances().send().await?;
 
         let mut jobs = Vec::new();
         for reservation in desc.reservations() {
             for instance in reservation.instances() {
-                let name_tag = instance
-                    .tags()
-                    .iter()
-                    .find(|t| t.key() == Some("Name"))
-                    .and_then(|t| t.value())
-                    .map(ToString::to_string);
-
                 jobs.push(Job {
                     id: instance.instance_id().unwrap_or_default().to_string(),
-                    ipv4: instance.public_ip_address().unwrap_or_default().to_string(),
-                    name: name_tag,
+                    addresses: addresses(instance),
+                    name: name_tag(instance.tags()),
+                    spot: is_spot(instance),
+                    running: is_running(instance),
                 });
             }
         }
@@ -192,43 +316,597 @@ impl Provider for AWSProvider {
         Ok(jobs)
     }
 
-    async fn tail(
+    async fn hourly_price(
         &self,
-        job_id: &str,
-        filename: &str,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let cfg = config();
+        spec: &JobSpec,
+    ) -> Result<Option<HourlyPrice>, Box<dyn std::error::Error + Send + Sync>> {
+        let aws = AwsConfig::load()?;
+        if !spec.spot.unwrap_or(aws.spot.enabled) {
+            return Ok(pricing::ec2_on_demand(&aws.instance_type));
+        }
+
+        let client = client(&aws).await;
+        let history = client
+            .describe_spot_price_history()
+            .instance_types(InstanceType::from(aws.instance_type.as_str()))
+            .product_descriptions("Linux/UNIX")
+            .max_results(1)
+            .send()
+            .await?;
+
+        Ok(history
+            .spot_price_history()
+            .first()
+            .and_then(|price| price.spot_price())
+            .and_then(|price| price.parse::<f64>().ok())
+            .map(HourlyPrice::usd))
+    }
+
+    async fn list_resources(
+        &self,
+    ) -> Result<Vec<Resource>, Box<dyn std::error::Error + Send + Sync>> {
+        let client = client(&AwsConfig::load()?).await;
+        let managed = || {
+            Filter::builder()
+                .name(format!("tag:{MANAGED_BY_KEY}"))
+                .values(MANAGED_BY_VALUE)
+                .build()
+        };
+
+        let mut resources = Vec::new();
+
+        let instances = client
+            .describe_instances()
+            .filters(managed())
+            .send()
+            .await?;
+        for instance in instances
+            .reservations()
+            .iter()
+            .flat_map(|reservation| reservation.instances())
+        {
+            let state = instance.state().and_then(|state| state.name());
+            if matches!(
+                state,
+                Some(InstanceStateName::Terminated | InstanceStateName::ShuttingDown)
+            ) {
+                continue;
+            }
+            resources.push(Resource {
+                kind: ResourceKind::Server,
+                id: instance.instance_id().unwrap_or_default().to_string(),
+                name: name_tag(instance.tags()).unwrap_or_default(),
+                created_at: instance
+                    .launch_time()
+                    .and_then(|time| u64::try_from(time.secs()).ok()),
+                busy: state == Some(&InstanceStateName::Pending),
+            });
+        }
 
-        if let Some(job) = self.get_job(job_id).await? {
-            let tcp = TcpStream::connect((job.ipv4.as_str(), 22))?;
-            let mut sess = Session::new()?;
-            sess.set_tcp_stream(tcp);
-            sess.handshake()?;
-            sess.userauth_pubkey_file("root", None, Path::new(&cfg.ssh_key_path), None)?;
+        let volumes = client.describe_volumes().filters(managed()).send().await?;
+        for volume in volumes
+            .volumes()
+            .iter()
+            .filter(|volume| tag(volume.tags(), VOLUME_KEY).is_none())
+        {
+            resources.push(Resource {
+                kind: ResourceKind::Volume,
+                id: volume.volume_id().unwrap_or_default().to_string(),
+                name: name_tag(volume.tags()).unwrap_or_default(),
+                created_at: volume
+                    .create_time()
+                    .and_then(|time| u64::try_from(time.secs()).ok()),
+                busy: volume.state() != Some(&VolumeState::Available),
+            });
+        }
 
-            let mut channel = sess.channel_session()?;
-            channel.exec(&format!("cat {filename}"))?;
+        let key_pairs = client
+            .describe_key_pairs()
+            .filters(managed())
+            .send()
+            .await?;
+        for key_pair in key_pairs.key_pairs() {
+            resources.push(Resource {
+                kind: ResourceKind::SshKey,
+                id: key_pair.key_name().unwrap_or_default().to_string(),
+                name: key_pair.key_name().unwrap_or_default().to_string(),
+                created_at: key_pair
+                    .create_time()
+                    .and_then(|time| u64::try_from(time.secs()).ok()),
+                busy: false,
+            });
+        }
 
-            let mut s = String::new();
-            channel.read_to_string(&mut s)?;
-            println!("{s}");
-            channel.wait_close()?;
-            println!("{}", channel.exit_status()?);
+        let groups = client
+            .describe_security_groups()
+            .filters(managed())
+            .send()
+            .await?;
+        for group in groups.security_groups() {
+            resources.push(Resource {
+                kind: ResourceKind::SecurityGroup,
+                id: group.group_id().unwrap_or_default().to_string(),
+                name: group.group_name().unwrap_or_default().to_string(),
+                created_at: None,
+                busy: false,
+            });
         }
 
+        Ok(resources)
+    }
+
+    async fn delete_resource(
+        &self,
+        resource: &Resource,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let client = client(&AwsConfig::load()?).await;
+        match resource.kind {
+            ResourceKind::Server => {
+                client
+                    .terminate_instances()
+                    .instance_ids(&resource.id)
+                    .send()
+                    .await?;
+            }
+            ResourceKind::Volume => {
+                client
+                    .delete_volume()
+                    .volume_id(&resource.id)
+                    .send()
+                    .await?;
+            }
+            ResourceKind::SshKey => {
+                client
+                    .delete_key_pair()
+                    .key_name(&resource.id)
+                    .send()
+                    .await?;
+            }
+            ResourceKind::SecurityGroup => {
+                client
+                    .delete_security_group()
+                    .group_id(&resource.id)
+                    .send()
+                    .await?;
+            }
+        }
         Ok(())
     }
 
-    async fn scp(
+    async fn list_volumes(&self) -> Result<Vec<Volume>, Box<dyn std::error::Error + Send + Sync>> {
+        let client = client(&AwsConfig::load()?).await;
+        let volumes = client
+            .describe_volumes()
+            .filters(Filter::builder().name("tag-key").values(VOLUME_KEY).build())
+            .send()
+            .await?;
+
+        Ok(volumes
+            .volumes()
+            .iter()
+            .map(|volume| Volume {
+                id: volume.volume_id().unwrap_or_default().to_string(),
+                name: tag(volume.tags(), VOLUME_KEY).unwrap_or_default(),
+                size_gb: f64::from(volume.size().unwrap_or_default()),
+                location: volume.availability_zone().unwrap_or_default().to_string(),
+                attached_to: volume
+                    .attachments()
+                    .first()
+                    .and_then(|attachment| attachment.instance_id())
+                    .map(ToString::to_string),
+            })
+            .collect())
+    }
+
+    async fn delete_volume(
         &self,
-        _job_id: &str,
-        _filename: &str,
-        _destination: &str,
+        name: &str,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let volume = self
+            .list_volumes()
+            .await?
+            .into_iter()
+            .find(|volume| volume.name == name)
+            .ok_or_else(|| format!("volume {name} not found"))?;
+        if let Some(instance) = volume.attached_to {
+            return Err(format!("volume {name} is attached to job {instance}").into());
+        }
+        let client = client(&AwsConfig::load()?).await;
+        client.delete_volume().volume_id(&volume.id).send().await?;
+        Ok(())
+    }
+
+    async fn bake_image(
+        &self,
+        toolchain: &str,
+        install_script: &str,
+    ) -> Result<Image, Box<dyn std::error::Error + Send + Sync>> {
+        let cfg = Config::new();
+        let aws = AwsConfig::load()?;
+        let client = client(&aws).await;
+        let name = format!("ffs-bake-{toolchain}-{}", timestamp());
+
+        let spec = JobSpec {
+            ssh_key: super::ephemeral_key(&cfg)?,
+            ..JobSpec::new(&name)
+        };
+        if let Some(key) = &spec.ssh_key {
+            import_key_pair(&client, &name, &key.public_key).await?;
+        }
+        let run_out = run_instances(&client, &aws, &cfg, &spec)
+            .instance_type(InstanceType::from(aws.instance_type.as_str()))
+            .set_subnet_id(aws.subnet_id.clone())
+            .send()
+            .await?;
+        let instance_id = run_out
+            .instances()
+            .first()
+            .and_then(Instance::instance_id)
+            .ok_or("no instance created")?
+            .to_string();
+
+        let baked = async {
+            client
+                .wait_until_instance_running()
+                .instance_ids(&instance_id)
+                .wait(Duration::from_secs(300))
+                .await?;
+            let desc = client
+                .describe_instances()
+                .instance_ids(&instance_id)
+                .send()
+                .await?;
+            let ssh_config = SshConfig::load()?;
+            let instance_addresses = desc
+                .reservations()
+                .first()
+                .and_then(|res| res.instances().first())
+                .map(addresses)
+                .unwrap_or_default();
+            let ip = jobs::reachable(&instance_addresses, &ssh_config)
+                .ok_or("the bake instance has no address to connect to")?
+                .ip
+                .to_string();
+            if let Some(key) = &spec.ssh_key {
+                ssh::add_identity(&instance_id, &key.private_key);
+            }
+            super::install_over_ssh(&instance_id, &ip, &ssh_config, install_script).await?;
+
+            let tags = |resource_type| {
+                TagSpecification::builder()
+                    .resource_type(resource_type)
+                    .tags(Tag::builder().key("Name").value(&name).build())
+                    .tags(
+                        Tag::builder()
+                            .key(MANAGED_BY_KEY)
+                            .value(MANAGED_BY_VALUE)
+                            .build(),
+                    )
+                    .tags(Tag::builder().key(TOOLCHAIN_KEY).value(toolchain).build())
+                    .build()
+            };
+            let image_id = client
+                .create_image()
+                .instance_id(&instance_id)
+                .name(&name)
+                .tag_specifications(tags(ResourceType::Image))
+                .tag_specifications(tags(ResourceType::Snapshot))
+                .send()
+                .await?
+                .image_id()
+                .ok_or("no image created")?
+                .to_string();
+            client
+                .wait_until_image_available()
+                .image_ids(&image_id)
+                .wait(IMAGE_TIMEOUT)
+                .await?;
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(image_id)
+        }
+        .await;
+
+        client
+            .terminate_instances()
+            .instance_ids(&instance_id)
+            .send()
+            .await?;
+        ssh::forget(&instance_id)?;
+        if spec.ssh_key.is_some() {
+            delete_key_pair(&client, &name).await?;
+        }
+
+        Ok(Image {
+            id: baked?,
+            name,
+            toolchain: toolchain.to_string(),
+            created_at: Some(timestamp()),
+        })
+    }
+
+    async fn list_images(&self) -> Result<Vec<Image>, Box<dyn std::error::Error + Send + Sync>> {
+        let client = client(&AwsConfig::load()?).await;
+        let images = client
+            .describe_images()
+            .owners("self")
+            .filters(
+                Filter::builder()
+                    .name(format!("tag:{MANAGED_BY_KEY}"))
+                    .values(MANAGED_BY_VALUE)
+                    .build(),
+            )
+            .filters(
+                Filter::builder()
+                    .name("tag-key")
+                    .values(TOOLCHAIN_KEY)
+                    .build(),
+            )
+            .send()
+            .await?;
+
+        Ok(images
+            .images()
+            .iter()
+            .map(|image| Image {
+                id: image.image_id().unwrap_or_default().to_string(),
+                name: image.name().unwrap_or_default().to_string(),
+                toolchain: tag(image.tags(), TOOLCHAIN_KEY).unwrap_or_default(),
+                created_at: image
+                    .creation_date()
+                    .and_then(|date| chrono::DateTime::parse_from_rfc3339(date).ok())
+                    .and_then(|date| u64::try_from(date.timestamp()).ok()),
+            })
+            .collect())
+    }
+
+    async fn delete_image(&self, id: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let client = client(&AwsConfig::load()?).await;
+        let images = client.describe_images().image_ids(id).send().await?;
+        // Deregistering an AMI keeps its snapshots, which are billed separately.
+        let snapshots: Vec<String> = images
+            .images()
+            .iter()
+            .flat_map(|image| image.block_device_mappings())
+            .filter_map(|mapping| mapping.ebs().and_then(|ebs| ebs.snapshot_id()))
+            .map(ToString::to_string)
+            .collect();
+        client.deregister_image().image_id(id).send().await?;
+        for snapshot in snapshots {
+            client
+                .delete_snapshot()
+                .snapshot_id(snapshot)
+                .send()
+                .await?;
+        }
         Ok(())
     }
 }
 
-fn config() -> Config {
-    Config::new()
+fn is_spot(instance: &Instance) -> bool {
+    instance.instance_lifecycle() == Some(&InstanceLifecycleType::Spot)
+}
+
+/// Returns the instance's public IPv4 address, if any, its primary private IPv4
+/// address and its IPv6 addresses, which are all public.
+fn addresses(instance: &Instance) -> Vec<Address> {
+    let ipv4 = instance.public_ip_address().and_then(Address::public);
+    let private = instance.private_ip_address().and_then(Address::private);
+    let ipv6 = instance
+        .network_interfaces()
+        .iter()
+        .flat_map(|interface| interface.ipv6_addresses())
+        .filter_map(|address| address.ipv6_address().and_then(Address::public));
+    ipv4.into_iter().chain(private).chain(ipv6).collect()
+}
+
+fn is_running(instance: &Instance) -> bool {
+    instance.state().and_then(|state| state.name()) == Some(&InstanceStateName::Running)
+}
+
+fn name_tag(tags: &[Tag]) -> Option<String> {
+    tag(tags, "Name")
+}
+
+fn tag(tags: &[Tag], key: &str) -> Option<String> {
+    tags.iter()
+        .find(|t| t.key() == Some(key))
+        .and_then(|t| t.value())
+        .map(ToString::to_string)
+}
+
+async fn subnet_zone(
+    client: &Client,
+    subnet_id: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let subnets = client
+        .describe_subnets()
+        .subnet_ids(subnet_id)
+        .send()
+        .await?;
+    subnets
+        .subnets()
+        .first()
+        .and_then(|subnet| subnet.availability_zone())
+        .map(ToString::to_string)
+        .ok_or_else(|| format!("subnet {subnet_id} not found").into())
+}
+
+/// Returns the id and availability zone of the persistent volume, creating it
+/// in the zone of `aws.subnet_id` (or the region's first zone) when it does not
+/// exist yet.
+async fn ensure_volume(
+    client: &Client,
+    aws: &AwsConfig,
+    volume: &VolumeSpec,
+) -> Result<(String, String), Box<dyn std::error::Error + Send + Sync>> {
+    let existing = client
+        .describe_volumes()
+        .filters(
+            Filter::builder()
+                .name(format!("tag:{VOLUME_KEY}"))
+                .values(&volume.name)
+                .build(),
+        )
+        .send()
+        .await?;
+    if let Some(existing) = existing.volumes().first() {
+        if existing.state() != Some(&VolumeState::Available) {
+            return Err(format!("volume {} is in use", volume.name).into());
+        }
+        return Ok((
+            existing.volume_id().unwrap_or_default().to_string(),
+            existing.availability_zone().unwrap_or_default().to_string(),
+        ));
+    }
+
+    let size = volume.size_gb.ok_or_else(|| {
+        format!(
+            "volume {} does not exist; give its size, e.g. {}:50G",
+            volume.name, volume.name
+        )
+    })?;
+    let zone = match &aws.subnet_id {
+        Some(subnet) => subnet_zone(client, subnet).await?,
+        None => format!("{}a", aws.region),
+    };
+    let created = client
+        .create_volume()
+        .availability_zone(&zone)
+        .size(i32::try_from(size)?)
+        .volume_type(VolumeType::Gp3)
+        .tag_specifications(
+            TagSpecification::builder()
+                .resource_type(ResourceType::Volume)
+                .tags(Tag::builder().key("Name").value(&volume.name).build())
+                .tags(
+                    Tag::builder()
+                        .key(MANAGED_BY_KEY)
+                        .value(MANAGED_BY_VALUE)
+                        .build(),
+                )
+                .tags(Tag::builder().key(VOLUME_KEY).value(&volume.name).build())
+                .build(),
+        )
+        .send()
+        .await?;
+    let volume_id = created.volume_id().ok_or("missing volume id")?.to_string();
+    client
+        .wait_until_volume_available()
+        .volume_ids(&volume_id)
+        .wait(Duration::from_secs(120))
+        .await?;
+    Ok((volume_id, zone))
+}
+
+/// Imports the ephemeral key of a job as a key pair named after the job.
+async fn import_key_pair(
+    client: &Client,
+    name: &str,
+    public_key: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    client
+        .import_key_pair()
+        .key_name(name)
+        .public_key_material(Blob::new(public_key.as_bytes()))
+        .tag_specifications(
+            TagSpecification::builder()
+                .resource_type(ResourceType::KeyPair)
+                .tags(
+                    Tag::builder()
+                        .key(MANAGED_BY_KEY)
+                        .value(MANAGED_BY_VALUE)
+                        .build(),
+                )
+                .build(),
+        )
+        .send()
+        .await?;
+    Ok(())
+}
+
+/// Deletes a key pair if ffs imported it, leaving keys registered by the user.
+async fn delete_key_pair(
+    client: &Client,
+    key_name: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let managed = client
+        .describe_key_pairs()
+        .key_names(key_name)
+        .filters(
+            Filter::builder()
+                .name(format!("tag:{MANAGED_BY_KEY}"))
+                .values(MANAGED_BY_VALUE)
+                .build(),
+        )
+        .send()
+        .await?;
+    if !managed.key_pairs().is_empty() {
+        client.delete_key_pair().key_name(key_name).send().await?;
+    }
+    Ok(())
+}
+
+/// Error codes after which another instance type, subnet or market is tried.
+const CAPACITY_ERRORS: &[&str] = &[
+    "InsufficientInstanceCapacity",
+    "InsufficientCapacity",
+    "SpotMaxPriceTooLow",
+    "MaxSpotInstanceCountExceeded",
+    "Unsupported",
+];
+
+fn is_capacity_error(code: Option<&str>) -> bool {
+    code.is_some_and(|code| CAPACITY_ERRORS.contains(&code))
+}
+
+/// Builds the parts of a `RunInstances` request shared by every launch attempt.
+fn run_instances(
+    client: &Client,
+    aws: &AwsConfig,
+    cfg: &Config,
+    spec: &JobSpec,
+) -> RunInstancesFluentBuilder {
+    let tag_spec = TagSpecification::builder()
+        .resource_type(ResourceType::Instance)
+        .tags(Tag::builder().key("Name").value(&spec.name).build())
+        .tags(
+            Tag::builder()
+                .key(MANAGED_BY_KEY)
+                .value(MANAGED_BY_VALUE)
+                .build(),
+        )
+        .build();
+
+    let mut request = client
+        .run_instances()
+        .image_id(aws.ami.clone())
+        .min_count(1)
+        .max_count(1)
+        .key_name(if spec.ssh_key.is_some() {
+            spec.name.clone()
+        } else {
+            cfg.ssh_key_name.clone()
+        })
+        .tag_specifications(tag_spec);
+    if spec.has_teardown() {
+        // Let the watchdog's shutdown terminate the instance so billing stops.
+        request = request.instance_initiated_shutdown_behavior(ShutdownBehavior::Terminate);
+    }
+    for group in &aws.security_group_ids {
+        request = request.security_group_ids(group);
+    }
+    if let Some(profile) = &aws.iam_instance_profile {
+        request = request.iam_instance_profile(
+            IamInstanceProfileSpecification::builder()
+                .name(profile)
+                .build(),
+        );
+    }
+    request
+}
+
+async fn client(config: &AwsConfig) -> Client {
+    let region_provider = RegionProviderChain::first_try(Some(Region::new(config.region.clone())))
+        .or_default_provider();
+    let shared_config = aws_config::from_env().region(region_provider).load().await;
+    Client::new(&shared_config)
 }
@@ -1,33 +1,94 @@
-use std::io::prelude::*;
-use std::net::TcpStream;
-use std::path::Path;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::time::Duration;
 
 use async_trait::async_trait;
 use hcloud::apis::configuration::Configuration;
+use hcloud::apis::images_api::{DeleteImageParams, GetImageParams, ListImagesParams};
+use hcloud::apis::server_types_api::ListServerTypesParams;
 use hcloud::apis::servers_api;
-use hcloud::apis::servers_api::{CreateServerParams, DeleteServerParams, ListServersParams};
-use hcloud::models::CreateServerRequest;
-use ssh2::Session;
-
-use super::Provider;
-use crate::config::Config;
-use crate::jobs::Job;
+use hcloud::apis::servers_api::{
+    CreateImageParams, CreateServerParams, DeleteServerParams, ListServersParams,
+};
+use hcloud::apis::ssh_keys_api::{CreateSshKeyParams, DeleteSshKeyParams, ListSshKeysParams};
+use hcloud::apis::volumes_api::{CreateVolumeParams, DeleteVolumeParams, ListVolumesParams};
+use hcloud::apis::{images_api, server_types_api, ssh_keys_api, volumes_api};
+use hcloud::models::server::Status;
+use hcloud::models::{
+    create_image_request, image, CreateImageRequest, CreateServerRequest,
+    CreateServerRequestFirewalls, CreateServerRequestPublicNet, CreateSshKeyRequest,
+    CreateVolumeRequest, Server,
+};
+
+use super::{
+    Image, JobSpec, Provider, Resource, ResourceKind, Volume, VolumeSpec, MANAGED_BY_KEY,
+    MANAGED_BY_VALUE, TOOLCHAIN_KEY, VOLUME_KEY,
+};
+use crate::config::{Config, HetznerConfig, SshConfig};
+use crate::jobs::{self, Address, Job};
+use crate::pricing::HourlyPrice;
+use crate::ssh;
+use crate::utils::timestamp;
+
+const BAKE_POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// Snapshots of a full toolchain can take a while to be created.
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(60 * 60);
 
 #[async_trait]
 impl Provider for HetznerProvider {
-    async fn start_job(&self, name: &str) -> Result<Job, Box<dyn std::error::Error + Send + Sync>> {
+    async fn start_job(
+        &self,
+        spec: &JobSpec,
+    ) -> Result<Job, Box<dyn std::error::Error + Send + Sync>> {
+        if spec.spot == Some(true) {
+            return Err("spot instances are only supported on AWS".into());
+        }
+        let name = spec.name.as_str();
         let config = Config::new();
-        let mut configuration = Configuration::new();
-        configuration.bearer_access_token = Some(config.hcloud_api_token);
+        let hetzner = HetznerConfig::load()?;
+        let configuration = api_configuration(&hetzner);
 
+        let volume = match &spec.volume {
+            Some(volume) => Some(ensure_volume(&configuration, &hetzner, volume).await?),
+            None => None,
+        };
+        let volume_devices: Vec<String> = volume
+            .iter()
+            .map(|id| format!("/dev/disk/by-id/scsi-0HC_Volume_{id}"))
+            .collect();
+
+        let firewalls = hetzner
+            .firewalls
+            .iter()
+            .map(|&firewall| CreateServerRequestFirewalls { firewall })
+            .collect::<Vec<_>>();
+        let ssh_key_name = match &spec.ssh_key {
+            Some(key) => create_ssh_key(&configuration, name, &key.public_key).await?,
+            None => config.ssh_key_name,
+        };
+        let baked = hetzner.baked_image.is_some();
         let params = CreateServerParams {
             create_server_request: Some(CreateServerRequest {
                 name: name.to_string(),
-                image: config.image,
-                server_type: config.server_type,
-                location: Some(config.location),
-                ssh_keys: Some(vec![config.ssh_key_name]),
-                user_data: Some(config.user_data),
+                image: hetzner.baked_image.unwrap_or(hetzner.image),
+                server_type: hetzner.server_type,
+                location: Some(hetzner.location),
+                firewalls: (!firewalls.is_empty()).then_some(firewalls),
+                labels: Some(HashMap::from([(
+                    MANAGED_BY_KEY.to_string(),
+                    MANAGED_BY_VALUE.to_string(),
+                )])),
+                ssh_keys: Some(vec![ssh_key_name]),
+                user_data: spec.user_data(&config.user_data, false, &volume_devices)?,
+                volumes: volume.map(|id| vec![id]),
+                automount: volume.map(|_| false),
+                public_net: spec.ipv6_only.then(|| {
+                    Box::new(CreateServerRequestPublicNet {
+                        enable_ipv4: Some(false),
+                        enable_ipv6: Some(true),
+                        ..Default::default()
+                    })
+                }),
                 ..Default::default()
             }),
         };
@@ -35,16 +96,16 @@ impl Provider for HetznerProvider {
 
         let job = Job {
             id: res.server.id.to_string(),
-            ipv4: res.server.public_net.ipv4.unwrap().ip,
+            addresses: addresses(&res.server),
             name: Some(name.to_string()),
+            spot: false,
+            running: res.server.status == Status::Running,
         };
 
-        let ip = job.ipv4.clone();
-        let key_path = config.ssh_key_path.clone();
-        tokio::spawn(async move {
-            let _ =
-                tokio::task::spawn_blocking(move || super::install_over_ssh(&ip, &key_path)).await;
-        });
+        super::remember_keys(&job.id, spec)?;
+        if !baked {
+            super::spawn_install(&job)?;
+        }
 
         Ok(job)
     }
@@ -53,15 +114,11 @@ impl Provider for HetznerProvider {
         &self,
         id: &str,
     ) -> Result<Option<Job>, Box<dyn std::error::Error + Send + Sync>> {
-        let config = Config::new();
-        let mut configuration = Configuration::new();
-        configuration.bearer_access_token = Some(config.hcloud_api_token);
+        let configuration = api_configuration(&HetznerConfig::load()?);
 
         let server = servers_api::get_server(
             &configuration,
-            hcloud::apis::servers_api::GetServerParams {
-                id: id.parse::<i64>().unwrap(),
-            },
+            hcloud::apis::servers_api::GetServerParams { id: hcloud_id(id)? },
         )
         .await?
         .server;
@@ -71,8 +128,10 @@ impl Provider for HetznerProvider {
             |server| {
                 Ok(Some(Job {
                     id: server.id.to_string(),
-                    ipv4: server.public_net.ipv4.unwrap().ip,
+                    addresses: addresses(&server),
                     name: Some(server.name),
+                    spot: false,
+                    running: server.status == Status::Running,
                 }))
             },
         )
@@ -82,25 +141,27 @@ impl Provider for HetznerProvider {
         &self,
         job_id: &str,
     ) -> Result<Job, Box<dyn std::error::Error + Send + Sync>> {
-        let config = Config::new();
-        let mut configuration = Configuration::new();
-        configuration.bearer_access_token = Some(config.hcloud_api_token);
-        let params = DeleteServerParams {
-            id: job_id.parse::<i64>().unwrap(),
-        };
-        servers_api::delete_server(&configuration, params).await?;
+        let configuration = api_configuration(&HetznerConfig::load()?);
+        let id = hcloud_id(job_id)?;
+        let server = servers_api::get_server(&configuration, servers_api::GetServerParams { id })
+            .await?
+            .server;
+        servers_api::delete_server(&configuration, DeleteServerParams { id }).await?;
+        if let Some(server) = server {
+            delete_ssh_keys(&configuration, &server.name).await?;
+        }
 
         Ok(Job {
             id: job_id.to_string(),
-            ipv4: String::new(),
+            addresses: Vec::new(),
             name: None,
+            spot: false,
+            running: false,
         })
     }
 
     async fn list_jobs(&self) -> Result<Vec<Job>, Box<dyn std::error::Error + Send + Sync>> {
-        let config = Config::new();
-        let mut configuration = Configuration::new();
-        configuration.bearer_access_token = Some(config.hcloud_api_token);
+        let configuration = api_configuration(&HetznerConfig::load()?);
 
         let servers = servers_api::list_servers(&configuration, ListServersParams::default())
             .await?
@@ -110,96 +171,278 @@ impl Provider for HetznerProvider {
             .into_iter()
             .map(|server| Job {
                 id: server.id.to_string(),
-                ipv4: server.public_net.ipv4.unwrap().ip,
+                addresses: addresses(&server),
                 name: Some(server.name),
+                spot: false,
+                running: server.status == Status::Running,
             })
             .collect();
 
         Ok(jobs)
     }
 
-    async fn tail(
+    async fn hourly_price(
         &self,
-        id: &str,
-        filename: &str,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let config = Config::new();
-        let mut configuration = Configuration::new();
-        configuration.bearer_access_token = Some(config.hcloud_api_token);
+        _spec: &JobSpec,
+    ) -> Result<Option<HourlyPrice>, Box<dyn std::error::Error + Send + Sync>> {
+        let hetzner = HetznerConfig::load()?;
+        let configuration = api_configuration(&hetzner);
 
-        let job = self.get_job(id).await?;
-
-        let ipv4 = job.unwrap().ipv4;
-        let tcp = TcpStream::connect((ipv4.as_str(), 22))?;
-        let mut sess = Session::new()?;
-        sess.set_tcp_stream(tcp);
-        sess.handshake()?;
+        let server_types = server_types_api::list_server_types(
+            &configuration,
+            ListServerTypesParams {
+                name: Some(hetzner.server_type.clone()),
+                ..Default::default()
+            },
+        )
+        .await?
+        .server_types;
+
+        Ok(server_types
+            .iter()
+            .flat_map(|server_type| &server_type.prices)
+            .find(|price| price.location == hetzner.location)
+            .and_then(|price| price.price_hourly.gross.parse::<f64>().ok())
+            .map(HourlyPrice::eur))
+    }
 
-        // Authenticate using a private key
-        sess.userauth_pubkey_file("root", None, Path::new(&config.ssh_key_path), None)?;
+    async fn list_resources(
+        &self,
+    ) -> Result<Vec<Resource>, Box<dyn std::error::Error + Send + Sync>> {
+        let configuration = api_configuration(&HetznerConfig::load()?);
+        let label_selector = Some(format!("{MANAGED_BY_KEY}={MANAGED_BY_VALUE}"));
 
-        // Open a channel
-        let mut channel = sess.channel_session()?;
+        let servers = servers_api::list_servers(
+            &configuration,
+            ListServersParams {
+                label_selector: label_selector.clone(),
+                ..Default::default()
+            },
+        )
+        .await?
+        .servers;
+        let volumes = volumes_api::list_volumes(
+            &configuration,
+            ListVolumesParams {
+                label_selector: Some(format!("{MANAGED_BY_KEY}={MANAGED_BY_VALUE},!{VOLUME_KEY}")),
+                ..Default::default()
+            },
+        )
+        .await?
+        .volumes;
+        let ssh_keys = ssh_keys_api::list_ssh_keys(
+            &configuration,
+            ListSshKeysParams {
+                label_selector,
+                ..Default::default()
+            },
+        )
+        .await?
+        .ssh_keys;
+
+        let servers = servers.into_iter().map(|server| Resource {
+            kind: ResourceKind::Server,
+            id: server.id.to_string(),
+            name: server.name,
+            created_at: created_at(&server.created),
+            busy: matches!(server.status, Status::Initializing | Status::Starting),
+        });
+        let volumes = volumes.into_iter().map(|volume| Resource {
+            kind: ResourceKind::Volume,
+            id: volume.id.to_string(),
+            name: volume.name,
+            created_at: created_at(&volume.created),
+            busy: volume.server.is_some(),
+        });
+        let ssh_keys = ssh_keys.into_iter().map(|key| Resource {
+            kind: ResourceKind::SshKey,
+            id: key.id.to_string(),
+            name: key.name,
+            created_at: created_at(&key.created),
+            busy: false,
+        });
 
-        // Execute command to read log file
-        channel.exec(&format!("cat {}", &filename))?;
+        Ok(servers.chain(volumes).chain(ssh_keys).collect())
+    }
 
-        // Read the output
-        let mut s = String::new();
-        channel.read_to_string(&mut s)?;
+    async fn delete_resource(
+        &self,
+        resource: &Resource,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let configuration = api_configuration(&HetznerConfig::load()?);
+        let id = hcloud_id(&resource.id)?;
+        match resource.kind {
+            ResourceKind::Server => {
+                servers_api::delete_server(&configuration, DeleteServerParams { id }).await?;
+            }
+            ResourceKind::Volume => {
+                volumes_api::delete_volume(&configuration, DeleteVolumeParams { id }).await?;
+            }
+            ResourceKind::SshKey => {
+                ssh_keys_api::delete_ssh_key(&configuration, DeleteSshKeyParams { id }).await?;
+            }
+            ResourceKind::SecurityGroup => {
+                return Err("Hetzner has no security groups".into());
+            }
+        }
+        Ok(())
+    }
 
-        // Print the logs
-        println!("{s}");
+    async fn list_volumes(&self) -> Result<Vec<Volume>, Box<dyn std::error::Error + Send + Sync>> {
+        let configuration = api_configuration(&HetznerConfig::load()?);
+        let volumes = volumes_api::list_volumes(
+            &configuration,
+            ListVolumesParams {
+                label_selector: Some(VOLUME_KEY.to_string()),
+                ..Default::default()
+            },
+        )
+        .await?
+        .volumes;
 
-        // Close the channel
-        channel.wait_close()?;
-        println!("{}", channel.exit_status()?);
+        Ok(volumes
+            .into_iter()
+            .map(|volume| Volume {
+                id: volume.id.to_string(),
+                name: volume
+                    .labels
+                    .get(VOLUME_KEY)
+                    .cloned()
+                    .unwrap_or(volume.name),
+                size_gb: f64::from(volume.size),
+                location: volume.location.name,
+                attached_to: volume.server.map(|server| server.to_string()),
+            })
+            .collect())
+    }
 
+    async fn delete_volume(
+        &self,
+        name: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let volume = self
+            .list_volumes()
+            .await?
+            .into_iter()
+            .find(|volume| volume.name == name)
+            .ok_or_else(|| format!("volume {name} not found"))?;
+        if let Some(server) = volume.attached_to {
+            return Err(format!("volume {name} is attached to job {server}").into());
+        }
+        let configuration = api_configuration(&HetznerConfig::load()?);
+        let id = hcloud_id(&volume.id)?;
+        volumes_api::delete_volume(&configuration, DeleteVolumeParams { id }).await?;
         Ok(())
     }
 
-    async fn scp(
+    async fn bake_image(
         &self,
-        id: &str,
-        filename: &str,
-        destination: &str,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        toolchain: &str,
+        install_script: &str,
+    ) -> Result<Image, Box<dyn std::error::Error + Send + Sync>> {
         let config = Config::new();
-        let mut configuration = Configuration::new();
-        configuration.bearer_access_token = Some(config.hcloud_api_token);
-
-        let job = self.get_job(id).await?;
-
-        let ipv4 = job.unwrap().ipv4;
-        let tcp = TcpStream::connect((ipv4.as_str(), 22))?;
-        let mut sess = Session::new()?;
-        sess.set_tcp_stream(tcp);
-        sess.handshake()?;
-
-        let mut channel = sess.channel_session()?;
-        channel.exec(&format!("scp -r root@{ipv4}:{filename} {destination}"))?;
-
-        // Read and print output in real-time
-        let mut buffer = [0; 1024];
-        loop {
-            match channel.read(&mut buffer) {
-                Ok(0) => break,
-                Ok(n) => {
-                    let output = String::from_utf8_lossy(&buffer[..n]);
-                    print!("{output}");
-                }
-                Err(e) => {
-                    if e.kind() == std::io::ErrorKind::WouldBlock {
-                        continue;
-                    }
-                    return Err(Box::new(e));
-                }
+        let hetzner = HetznerConfig::load()?;
+        let configuration = api_configuration(&hetzner);
+        let name = format!("ffs-bake-{toolchain}-{}", timestamp());
+
+        let labels = HashMap::from([(MANAGED_BY_KEY.to_string(), MANAGED_BY_VALUE.to_string())]);
+        let ssh_key = super::ephemeral_key(&config)?;
+        let ssh_key_name = match &ssh_key {
+            Some(key) => create_ssh_key(&configuration, &name, &key.public_key).await?,
+            None => config.ssh_key_name.clone(),
+        };
+        let server = servers_api::create_server(
+            &configuration,
+            CreateServerParams {
+                create_server_request: Some(CreateServerRequest {
+                    name: name.clone(),
+                    image: hetzner.image,
+                    server_type: hetzner.server_type,
+                    location: Some(hetzner.location),
+                    labels: Some(labels.clone()),
+                    ssh_keys: Some(vec![ssh_key_name]),
+                    ..Default::default()
+                }),
+            },
+        )
+        .await?
+        .server;
+
+        let baked = async {
+            let ssh_config = SshConfig::load()?;
+            let ip = jobs::reachable(&addresses(&server), &ssh_config)
+                .ok_or("the bake server has no address to connect to")?
+                .ip
+                .to_string();
+            let server_id = server.id.to_string();
+            if let Some(key) = &ssh_key {
+                ssh::add_identity(&server_id, &key.private_key);
             }
+            super::install_over_ssh(&server_id, &ip, &ssh_config, install_script).await?;
+
+            let mut image_labels = labels.clone();
+            image_labels.insert(TOOLCHAIN_KEY.to_string(), toolchain.to_string());
+            let image = servers_api::create_image(
+                &configuration,
+                CreateImageParams {
+                    id: server.id,
+                    create_image_request: Some(CreateImageRequest {
+                        description: Some(name.clone()),
+                        labels: Some(image_labels),
+                        r#type: Some(create_image_request::Type::Snapshot),
+                    }),
+                },
+            )
+            .await?
+            .image
+            .ok_or("no snapshot created")?;
+            wait_for_snapshot(&configuration, image.id).await?;
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(image.id)
+        }
+        .await;
+
+        servers_api::delete_server(&configuration, DeleteServerParams { id: server.id }).await?;
+        ssh::forget(&server.id.to_string())?;
+        if ssh_key.is_some() {
+            delete_ssh_keys(&configuration, &name).await?;
         }
 
-        channel.wait_close()?;
-        println!("{}", channel.exit_status()?);
+        Ok(Image {
+            id: baked?.to_string(),
+            name,
+            toolchain: toolchain.to_string(),
+            created_at: Some(timestamp()),
+        })
+    }
+
+    async fn list_images(&self) -> Result<Vec<Image>, Box<dyn std::error::Error + Send + Sync>> {
+        let configuration = api_configuration(&HetznerConfig::load()?);
+        let images = images_api::list_images(
+            &configuration,
+            ListImagesParams {
+                label_selector: Some(format!(
+                    "{MANAGED_BY_KEY}={MANAGED_BY_VALUE},{TOOLCHAIN_KEY}"
+                )),
+                ..Default::default()
+            },
+        )
+        .await?
+        .images;
+
+        Ok(images
+            .into_iter()
+            .map(|image| Image {
+                id: image.id.to_string(),
+                name: image.description,
+                toolchain: image.labels.get(TOOLCHAIN_KEY).cloned().unwrap_or_default(),
+                created_at: created_at(&image.created),
+            })
+            .collect())
+    }
 
+    async fn delete_image(&self, id: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let configuration = api_configuration(&HetznerConfig::load()?);
+        images_api::delete_image(&configuration, DeleteImageParams { id: hcloud_id(id)? }).await?;
         Ok(())
     }
 }
@@ -219,3 +462,164 @@ impl HetznerProvider {
         Self {}
     }
 }
+
+/// Returns the id of the persistent volume, creating it in the configured
+/// location when it does not exist yet.
+async fn ensure_volume(
+    configuration: &Configuration,
+    hetzner: &HetznerConfig,
+    volume: &VolumeSpec,
+) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+    let existing = volumes_api::list_volumes(
+        configuration,
+        ListVolumesParams {
+            label_selector: Some(format!("{VOLUME_KEY}={}", volume.name)),
+            ..Default::default()
+        },
+    )
+    .await?
+    .volumes
+    .into_iter()
+    .next();
+    if let Some(existing) = existing {
+        if let Some(server) = existing.server {
+            return Err(format!("volume {} is attached to job {server}", volume.name).into());
+        }
+        if existing.location.name != hetzner.location {
+            return Err(format!(
+                "volume {} is in {}, not {}",
+                volume.name, existing.location.name, hetzner.location
+            )
+            .into());
+        }
+        return Ok(existing.id);
+    }
+
+    let size = volume.size_gb.ok_or_else(|| {
+        format!(
+            "volume {} does not exist; give its size, e.g. {}:50G",
+            volume.name, volume.name
+        )
+    })?;
+    let created = volumes_api::create_volume(
+        configuration,
+        CreateVolumeParams {
+            create_volume_request: Some(CreateVolumeRequest {
+                name: volume.name.clone(),
+                size: i32::try_from(size)?,
+                location: Some(hetzner.location.clone()),
+                format: Some("ext4".to_string()),
+                labels: Some(HashMap::from([
+                    (MANAGED_BY_KEY.to_string(), MANAGED_BY_VALUE.to_string()),
+                    (VOLUME_KEY.to_string(), volume.name.clone()),
+                ])),
+                ..Default::default()
+            }),
+        },
+    )
+    .await?;
+    Ok(created.volume.id)
+}
+
+/// Waits until a snapshot is available.
+async fn wait_for_snapshot(
+    configuration: &Configuration,
+    id: i64,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let started = std::time::Instant::now();
+    while started.elapsed() < SNAPSHOT_TIMEOUT {
+        let snapshot = images_api::get_image(configuration, GetImageParams { id })
+            .await?
+            .image;
+        if snapshot.status == image::Status::Available {
+            return Ok(());
+        }
+        tokio::time::sleep(BAKE_POLL_INTERVAL).await;
+    }
+    Err(format!("snapshot {id} was not available after {SNAPSHOT_TIMEOUT:?}").into())
+}
+
+fn created_at(created: &str) -> Option<u64> {
+    chrono::DateTime::parse_from_rfc3339(created)
+        .ok()
+        .and_then(|created| u64::try_from(created.timestamp()).ok())
+}
+
+/// Registers the ephemeral key of a job under the job's name, returning the name.
+async fn create_ssh_key(
+    configuration: &Configuration,
+    name: &str,
+    public_key: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let key = ssh_keys_api::create_ssh_key(
+        configuration,
+        CreateSshKeyParams {
+            create_ssh_key_request: Some(CreateSshKeyRequest {
+                name: name.to_string(),
+                public_key: public_key.to_string(),
+                labels: Some(HashMap::from([(
+                    MANAGED_BY_KEY.to_string(),
+                    MANAGED_BY_VALUE.to_string(),
+                )])),
+            }),
+        },
+    )
+    .await?
+    .ssh_key;
+    Ok(key.name)
+}
+
+/// Deletes the ephemeral keys ffs registered for the job named `name`.
+async fn delete_ssh_keys(
+    configuration: &Configuration,
+    name: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let keys = ssh_keys_api::list_ssh_keys(
+        configuration,
+        ListSshKeysParams {
+            name: Some(name.to_string()),
+            label_selector: Some(format!("{MANAGED_BY_KEY}={MANAGED_BY_VALUE}")),
+            ..Default::default()
+        },
+    )
+    .await?
+    .ssh_keys;
+    for key in keys {
+        ssh_keys_api::delete_ssh_key(configuration, DeleteSshKeyParams { id: key.id }).await?;
+    }
+    Ok(())
+}
+
+/// Returns the server's public IPv4 and IPv6 addresses, if enabled, and its
+/// addresses in the private networks it is attached to.
+fn addresses(server: &Server) -> Vec<Address> {
+    let public_net = &server.public_net;
+    let ipv4 = public_net
+        .ipv4
+        .as_ref()
+        .and_then(|ipv4| Address::public(&ipv4.ip));
+    // The server is given a /64 network and answers on its first address.
+    let ipv6 = public_net.ipv6.as_ref().and_then(|ipv6| {
+        let network: Ipv6Addr = ipv6.ip.split('/').next()?.parse().ok()?;
+        Some(Address {
+            ip: IpAddr::V6(Ipv6Addr::from(u128::from(network) | 1)),
+            public: true,
+        })
+    });
+    let private = server
+        .private_net
+        .iter()
+        .filter_map(|net| net.ip.as_deref().and_then(Address::private));
+    ipv4.into_iter().chain(ipv6).chain(private).collect()
+}
+
+fn hcloud_id(id: &str) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+    id.parse::<i64>()
+        .map_err(|_| format!("invalid Hetzner resource id `{id}`").into())
+}
+
+fn api_configuration(config: &HetznerConfig) -> Configuration {
+    let mut configuration = Configuration::new();
+    configuration.bearer_access_token = Some(config.api_token.clone());
+    configuration
+}
@@ -1,11 +1,12 @@
-use std::io::prelude::*;
-use std::net::TcpStream;
-use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
 
 use async_trait::async_trait;
-use ssh2::Session;
 
+use crate::config::{Config, ConfigError, Settings, SshConfig, StorageConfig};
 use crate::jobs::Job;
+use crate::pricing::HourlyPrice;
+use crate::ssh::{self, Connection, KeyPair, SshError};
 
 const INSTALL_SCRIPT: &str = r#"#!/bin/bash
 set -e
@@ -20,27 +21,571 @@ curl -L https://foundry.paradigm.xyz | bash
 "$HOME"/.cargo/bin/cargo install --locked echidna
 "$HOME"/.cargo/bin/cargo install --locked --git https://github.com/crytic/medusa
 "$HOME"/.cargo/bin/cargo install --locked --git https://github.com/crytic/halmos
+mkdir -p /root/ffs
+touch /root/ffs/installed
 "#;
 
-fn install_over_ssh(
+/// Directory on the job holding the campaign, its status and the watchdog.
+pub const JOB_DIR: &str = "/root/ffs";
+
+/// tmux session the campaign runs in on the job, attached to by `ffs attach`.
+pub const CAMPAIGN_SESSION: &str = "ffs";
+
+/// Host-side watchdog installed through user data. It waits for the install to
+/// finish, runs the campaign in a tmux session until it exits or the deadline
+/// passes, runs the optional upload hook, records why it stopped in `status`,
+/// syncs the artifacts a last time and powers off. Without tmux the campaign
+/// runs detached, and cannot be attached to.
+const WATCHDOG_SCRIPT: &str = r#"#!/bin/bash
+MAX_SECONDS="$1"
+STOP_ON_FINISH="$2"
+SESSION="$3"
+DEADLINE=$(( $(date +%s) + MAX_SECONDS ))
+remaining() {
+  if [ "$MAX_SECONDS" -eq 0 ]; then echo 0; else echo $(( DEADLINE - $(date +%s) )); fi
+}
+expired() {
+  [ "$MAX_SECONDS" -gt 0 ] && [ "$(remaining)" -le 0 ]
+}
+start_campaign() {
+  command -v tmux > /dev/null || apt-get -o DPkg::Lock::Timeout=600 install -y tmux > /root/ffs/tmux-install.log 2>&1
+  if command -v tmux > /dev/null; then
+    # The campaign waits until its output is logged before starting.
+    tmux new-session -d -s "$SESSION" -x 220 -y 50 "tmux wait-for $SESSION-logged; cd /root && bash /root/ffs/campaign.sh"
+    tmux pipe-pane -t "$SESSION" -o "cat >> /root/ffs/campaign.log"
+    tmux wait-for -S "$SESSION-logged"
+    running() { tmux has-session -t "$SESSION" 2> /dev/null; }
+    stop_campaign() { tmux kill-session -t "$SESSION"; }
+  else
+    (cd /root && exec bash /root/ffs/campaign.sh > /root/ffs/campaign.log 2>&1) &
+    CAMPAIGN_PID=$!
+    running() { kill -0 "$CAMPAIGN_PID" 2> /dev/null; }
+    stop_campaign() { kill "$CAMPAIGN_PID"; }
+  fi
+}
+REASON=finished
+if [ -f /root/ffs/campaign.sh ]; then
+  while [ ! -f /root/ffs/installed ] || { [ -f /root/ffs/restore-pending ] && [ ! -f /root/ffs/restored ]; }; do
+    expired && break
+    sleep 10
+  done
+  if expired; then
+    REASON=max-duration
+  else
+    start_campaign
+    while running; do
+      if expired; then
+        stop_campaign
+        REASON=max-duration
+        break
+      fi
+      sleep 5
+    done
+  fi
+elif [ "$MAX_SECONDS" -gt 0 ]; then
+  sleep "$MAX_SECONDS"
+  REASON=max-duration
+else
+  exit 0
+fi
+grep -qs interrupted /root/ffs/status && exit 0
+[ -x /root/ffs/upload.sh ] && /root/ffs/upload.sh
+echo "$REASON" > /root/ffs/status
+[ -f /root/ffs/sync.sh ] && bash /root/ffs/sync.sh 0
+if [ "$REASON" = max-duration ] || [ "$STOP_ON_FINISH" = 1 ]; then
+  shutdown -h now
+fi
+"#;
+
+/// Label or tag marking cloud resources created by ffs.
+pub const MANAGED_BY_KEY: &str = "managed-by";
+pub const MANAGED_BY_VALUE: &str = "ffs";
+
+/// Label or tag naming the toolchain baked into an image.
+pub const TOOLCHAIN_KEY: &str = "ffs-toolchain";
+
+/// Toolchain installed by [`INSTALL_SCRIPT`] unless `toolchains.default` is set.
+pub const DEFAULT_TOOLCHAIN: &str = "default";
+
+/// Label or tag naming a persistent volume, which `ffs gc` never collects.
+pub const VOLUME_KEY: &str = "ffs-volume";
+
+/// Host key of the job's SSH server replaced by the one pinned for the job.
+const HOST_KEY_PATH: &str = "/etc/ssh/ssh_host_ed25519_key";
+
+/// Where a persistent volume is mounted on the job.
+const VOLUME_MOUNT: &str = "/mnt/ffs-volume";
+
+/// Mounts a persistent volume once the provider has attached it, formatting it
+/// on first use, and bind-mounts its directories over the corpus directories
+/// under `/root` so they survive the job.
+const VOLUME_SCRIPT: &str = r#"#!/bin/bash
+MOUNT="$1"
+PATHS="$2"
+shift 2
+for attempt in $(seq 60); do
+  for device in "$@"; do
+    [ -e "$device" ] && DEVICE="$device" && break 2
+  done
+  sleep 5
+done
+[ -z "$DEVICE" ] && { echo "volume not attached" >&2; exit 1; }
+blkid "$DEVICE" > /dev/null || mkfs.ext4 -q "$DEVICE"
+mkdir -p "$MOUNT"
+mount "$DEVICE" "$MOUNT"
+for path in $PATHS; do
+  mkdir -p "$MOUNT/$path" "/root/$path"
+  mount --bind "$MOUNT/$path" "/root/$path"
+done
+"#;
+
+/// Kind of cloud resource ffs may leave behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceKind {
+    Server,
+    Volume,
+    SshKey,
+    SecurityGroup,
+}
+
+impl std::fmt::Display for ResourceKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Server => write!(f, "server"),
+            Self::Volume => write!(f, "volume"),
+            Self::SshKey => write!(f, "ssh key"),
+            Self::SecurityGroup => write!(f, "security group"),
+        }
+    }
+}
+
+/// A cloud resource tagged as managed by ffs.
+#[derive(Debug, Clone)]
+pub struct Resource {
+    pub kind: ResourceKind,
+    pub id: String,
+    pub name: String,
+    /// Unix time the resource was created, when the provider reports it.
+    pub created_at: Option<u64>,
+    /// Whether a server is still provisioning, or a volume is attached.
+    pub busy: bool,
+}
+
+/// Spot interruption watcher installed through user data on spot instances. On
+/// the two-minute notice it runs the upload hook to save the corpus and marks the
+/// job as interrupted so it can be resumed.
+const SPOT_WATCHER_SCRIPT: &str = r#"#!/bin/bash
+IMDS=http://169.254.169.254/latest
+while true; do
+  TOKEN=$(curl -s -X PUT "$IMDS/api/token" -H "X-aws-ec2-metadata-token-ttl-seconds: 300")
+  if curl -sf -H "X-aws-ec2-metadata-token: $TOKEN" "$IMDS/meta-data/spot/instance-action" > /dev/null; then
+    [ -x /root/ffs/upload.sh ] && /root/ffs/upload.sh
+    echo interrupted > /root/ffs/status
+    [ -f /root/ffs/sync.sh ] && bash /root/ffs/sync.sh 0
+    exit 0
+  fi
+  sleep 5
+done
+"#;
+
+/// Artifact sync installed through user data when `storage.bucket` is set. It
+/// copies the configured directories and the job logs to the bucket every
+/// interval until the job records a `status`, using rclone configured from
+/// `storage.env`. With `restore PREFIX` it first copies a previous job's
+/// directories back and releases the campaign waiting for them.
+const SYNC_SCRIPT: &str = r#"#!/bin/bash
+set -a
+. /root/ffs/storage.env
+set +a
+ensure_rclone() {
+  command -v rclone > /dev/null || apt-get -o DPkg::Lock::Timeout=600 install -y rclone > /root/ffs/rclone-install.log 2>&1
+}
+sync_once() {
+  ensure_rclone || return
+  for path in $FFS_PATHS; do
+    [ -d "/root/$path" ] && rclone copy "/root/$path" "ffs:$FFS_BUCKET/$FFS_PREFIX/$path"
+  done
+  rclone copy /root/ffs "ffs:$FFS_BUCKET/$FFS_PREFIX/logs" --include '*.log' --include status
+}
+if [ "$1" = restore ]; then
+  until ensure_rclone; do sleep 10; done
+  for path in $FFS_PATHS; do
+    rclone copy "ffs:$FFS_BUCKET/$2/$path" "/root/$path"
+  done
+  touch /root/ffs/restored
+  exit 0
+fi
+sync_once
+[ "$1" -eq 0 ] && exit 0
+while [ ! -f /root/ffs/status ]; do
+  sleep "$1"
+  sync_once
+done
+"#;
+
+/// A persistent volume requested with `--volume NAME[:SIZE]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VolumeSpec {
+    pub name: String,
+    /// Size in GB, required when the volume does not exist yet.
+    pub size_gb: Option<u32>,
+    /// Directories under `/root` kept on the volume.
+    pub paths: Vec<String>,
+}
+
+impl std::str::FromStr for VolumeSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, size) = match s.split_once(':') {
+            Some((name, size)) => (name, Some(size)),
+            None => (s, None),
+        };
+        let valid_name = name.len() <= 63
+            && name.starts_with(|c: char| c.is_ascii_alphanumeric())
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid_name {
+            return Err(format!("invalid volume name `{name}`"));
+        }
+        let size_gb = size
+            .map(|size| {
+                size.trim_end_matches(['G', 'g'])
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|&size| size > 0)
+                    .ok_or_else(|| format!("invalid volume size `{size}`, expected e.g. 50G"))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            name: name.to_string(),
+            size_gb,
+            paths: Vec::new(),
+        })
+    }
+}
+
+/// A persistent volume kept across jobs.
+#[derive(Debug, Clone)]
+pub struct Volume {
+    pub id: String,
+    pub name: String,
+    pub size_gb: f64,
+    /// Hetzner location or EC2 availability zone.
+    pub location: String,
+    /// Id of the job it is attached to.
+    pub attached_to: Option<String>,
+}
+
+/// Where a job syncs its artifacts and how often.
+#[derive(Debug, Clone, Default)]
+pub struct ArtifactSync {
+    /// Variables configuring the rclone remote, written to a file only root can read.
+    pub env: Vec<(String, String)>,
+    /// Key prefix of this job's artifacts.
+    pub prefix: String,
+    pub interval: Duration,
+    /// Key prefix of a previous job whose directories are restored before the
+    /// campaign starts.
+    pub restore_from: Option<String>,
+}
+
+impl ArtifactSync {
+    #[must_use]
+    pub fn new(storage: &StorageConfig, prefix: &str) -> Self {
+        let mut env = vec![
+            ("RCLONE_CONFIG_FFS_TYPE", "s3".to_string()),
+            (
+                "RCLONE_CONFIG_FFS_PROVIDER",
+                if storage.endpoint.is_some() {
+                    "Other"
+                } else {
+                    "AWS"
+                }
+                .to_string(),
+            ),
+            ("RCLONE_CONFIG_FFS_REGION", storage.region.clone()),
+            ("FFS_BUCKET", storage.bucket.clone()),
+            ("FFS_PREFIX", prefix.to_string()),
+            ("FFS_PATHS", storage.paths.join(" ")),
+        ];
+        if let Some(endpoint) = &storage.endpoint {
+            env.push(("RCLONE_CONFIG_FFS_ENDPOINT", endpoint.clone()));
+        }
+        match (&storage.access_key_id, &storage.secret_access_key) {
+            (Some(access_key_id), Some(secret_access_key)) => {
+                env.push(("RCLONE_CONFIG_FFS_ACCESS_KEY_ID", access_key_id.clone()));
+                env.push((
+                    "RCLONE_CONFIG_FFS_SECRET_ACCESS_KEY",
+                    secret_access_key.clone(),
+                ));
+            }
+            _ => env.push(("RCLONE_CONFIG_FFS_ENV_AUTH", "true".to_string())),
+        }
+
+        Self {
+            env: env
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+            prefix: prefix.to_string(),
+            interval: storage.interval,
+            restore_from: None,
+        }
+    }
+}
+
+/// Returns whether user data is empty or a shell script ffs can append to.
+#[must_use]
+pub fn is_script(user_data: &str) -> bool {
+    user_data.is_empty() || user_data.starts_with("#!")
+}
+
+/// Quotes a value for a POSIX shell.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// What to run on a new job and when to tear it down.
+#[derive(Debug, Clone, Default)]
+pub struct JobSpec {
+    pub name: String,
+    pub campaign: Option<String>,
+    pub max_duration: Option<Duration>,
+    pub stop_on_finish: bool,
+    /// Whether to use spot capacity, overriding `aws.spot`.
+    pub spot: Option<bool>,
+    /// Maximum spot price, overriding `aws.spot_max_price`.
+    pub spot_max_price: Option<String>,
+    /// Whether the job gets a public IPv6 address only (Hetzner).
+    pub ipv6_only: bool,
+    /// Whether the campaign waits for a checkpoint to be restored before starting.
+    pub restore: bool,
+    /// Object storage the job syncs its artifacts to.
+    pub artifacts: Option<ArtifactSync>,
+    /// Persistent volume attached to the job.
+    pub volume: Option<VolumeSpec>,
+    /// SSH host key installed on the job and pinned once it is created.
+    pub host_key: Option<KeyPair>,
+    /// Ephemeral key registered with the provider, named after the job, and
+    /// authorized for the job only.
+    pub ssh_key: Option<KeyPair>,
+}
+
+impl JobSpec {
+    #[must_use]
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// Returns whether the job should tear itself down.
+    #[must_use]
+    pub const fn has_teardown(&self) -> bool {
+        self.max_duration.is_some() || self.stop_on_finish
+    }
+
+    /// Builds the user data for the job: the profile's own user data, followed by
+    /// the pinned host key, the campaign and the watchdog when a campaign or teardown was requested,
+    /// the artifact sync when storage is configured, and the interruption watcher
+    /// on spot instances. When a volume is attached, it is mounted first from the
+    /// first of `volume_devices` to appear.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a script is needed but the profile's user data is not a
+    /// shell script it can be appended to.
+    pub fn user_data(
+        &self,
+        profile_user_data: &str,
+        spot: bool,
+        volume_devices: &[String],
+    ) -> Result<Option<String>, String> {
+        if self.campaign.is_none()
+            && !self.has_teardown()
+            && self.artifacts.is_none()
+            && self.volume.is_none()
+            && self.host_key.is_none()
+            && !spot
+        {
+            return Ok((!profile_user_data.is_empty()).then(|| profile_user_data.to_string()));
+        }
+        let mut script = if profile_user_data.is_empty() {
+            "#!/bin/bash\n".to_string()
+        } else if is_script(profile_user_data) {
+            format!("{}\n", profile_user_data.trim_end())
+        } else {
+            return Err("user_data must be a shell script to run a campaign or watchdog".into());
+        };
+        script.push_str(&format!("mkdir -p {JOB_DIR}\n"));
+        if let Some(host_key) = &self.host_key {
+            script.push_str(&format!(
+                "(umask 077; cat > {HOST_KEY_PATH} <<'FFS_HOST_KEY'\n{}\nFFS_HOST_KEY\n)\n",
+                host_key.private_key.trim_end()
+            ));
+            script.push_str(&format!(
+                "echo {} > {HOST_KEY_PATH}.pub\n",
+                shell_quote(&host_key.public_key)
+            ));
+            script.push_str("systemctl restart ssh 2>/dev/null || systemctl restart sshd\n");
+        }
+        if let Some(volume) = &self.volume {
+            script.push_str(&format!(
+                "cat > {JOB_DIR}/volume.sh <<'FFS_VOLUME'\n{VOLUME_SCRIPT}FFS_VOLUME\n"
+            ));
+            let devices: Vec<String> = volume_devices.iter().map(|d| shell_quote(d)).collect();
+            script.push_str(&format!(
+                "bash {JOB_DIR}/volume.sh {VOLUME_MOUNT} {} {} > {JOB_DIR}/volume.log 2>&1\n",
+                shell_quote(&volume.paths.join(" ")),
+                devices.join(" ")
+            ));
+        }
+        if let Some(campaign) = &self.campaign {
+            script.push_str(&format!(
+                "cat > {JOB_DIR}/campaign.sh <<'FFS_CAMPAIGN'\n{campaign}\nFFS_CAMPAIGN\n"
+            ));
+        }
+        if self.restore {
+            script.push_str(&format!("touch {JOB_DIR}/restore-pending\n"));
+        }
+        if self.campaign.is_some() || self.has_teardown() {
+            script.push_str(&format!(
+                "cat > {JOB_DIR}/watchdog.sh <<'FFS_WATCHDOG'\n{WATCHDOG_SCRIPT}FFS_WATCHDOG\n"
+            ));
+            script.push_str(&format!(
+                "setsid nohup bash {JOB_DIR}/watchdog.sh {} {} {CAMPAIGN_SESSION} > {JOB_DIR}/watchdog.log 2>&1 &\n",
+                self.max_duration.map_or(0, |duration| duration.as_secs()),
+                u8::from(self.stop_on_finish)
+            ));
+        }
+        if let Some(artifacts) = &self.artifacts {
+            let env: String = artifacts
+                .env
+                .iter()
+                .map(|(key, value)| format!("{key}={}\n", shell_quote(value)))
+                .collect();
+            script.push_str(&format!(
+                "(umask 077; cat > {JOB_DIR}/storage.env <<'FFS_STORAGE'\n{env}FFS_STORAGE\n)\n"
+            ));
+            script.push_str(&format!(
+                "cat > {JOB_DIR}/sync.sh <<'FFS_SYNC'\n{SYNC_SCRIPT}FFS_SYNC\n"
+            ));
+            let interval = artifacts.interval.as_secs().max(60);
+            let restore = artifacts
+                .restore_from
+                .as_ref()
+                .map(|prefix| format!("bash {JOB_DIR}/sync.sh restore {}; ", shell_quote(prefix)))
+                .unwrap_or_default();
+            script.push_str(&format!(
+                "setsid nohup bash -c \"{restore}bash {JOB_DIR}/sync.sh {interval}\" > {JOB_DIR}/sync.log 2>&1 &\n"
+            ));
+        }
+        if spot {
+            script.push_str(&format!(
+                "cat > {JOB_DIR}/spot-watcher.sh <<'FFS_SPOT_WATCHER'\n{SPOT_WATCHER_SCRIPT}FFS_SPOT_WATCHER\n"
+            ));
+            script.push_str(&format!(
+                "setsid nohup bash {JOB_DIR}/spot-watcher.sh > {JOB_DIR}/spot-watcher.log 2>&1 &\n"
+            ));
+        }
+        Ok(Some(script))
+    }
+}
+
+/// A machine image with a toolchain preinstalled, baked by `ffs image bake`.
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub id: String,
+    pub name: String,
+    pub toolchain: String,
+    pub created_at: Option<u64>,
+}
+
+/// Returns the install script of a toolchain: the file named by
+/// `toolchains.<name>`, or the built-in script for the default toolchain.
+///
+/// # Errors
+///
+/// Returns an error if the toolchain is unknown or its script cannot be read.
+pub fn install_script(settings: &Settings, toolchain: &str) -> Result<String, ConfigError> {
+    let key = format!("toolchains.{toolchain}");
+    match settings.get(&key) {
+        Some(path) => std::fs::read_to_string(&path).map_err(|e| ConfigError::File {
+            path,
+            reason: e.to_string(),
+        }),
+        None if toolchain == DEFAULT_TOOLCHAIN => Ok(INSTALL_SCRIPT.to_string()),
+        None => Err(ConfigError::MissingKey(key)),
+    }
+}
+
+/// How long a new job gets to accept SSH connections before its install fails.
+const INSTALL_SSH_ATTEMPTS: u32 = 60;
+const INSTALL_SSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Waits for the job to accept SSH connections, then runs an install script on
+/// it and waits for the script to finish.
+async fn install_over_ssh(
+    job_id: &str,
     ip: &str,
-    key_path: &str,
+    ssh: &SshConfig,
+    script: &str,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let tcp = TcpStream::connect((ip, 22))?;
-    let mut sess = Session::new()?;
-    sess.set_tcp_stream(tcp);
-    sess.handshake()?;
-    sess.userauth_pubkey_file("root", None, Path::new(key_path), None)?;
-
-    let mut channel = sess.channel_session()?;
-    channel.exec("bash -s")?;
-    channel.write_all(INSTALL_SCRIPT.as_bytes())?;
-    channel.send_eof()?;
-    channel.wait_close()?;
+    let connection =
+        ssh::wait_until_reachable(job_id, ip, ssh, INSTALL_SSH_ATTEMPTS, INSTALL_SSH_INTERVAL)
+            .await?;
+    let script = script.to_string();
+    tokio::task::spawn_blocking(move || connection.run_script(&script)).await??;
+    Ok(())
+}
 
+/// Installs the configured toolchain on a new job in the background, unless it
+/// was started from a baked image.
+fn spawn_install(job: &Job) -> Result<(), ConfigError> {
+    let settings = Settings::load()?;
+    let toolchain = settings.get("toolchain").unwrap_or_default();
+    let script = install_script(&settings, &toolchain)?;
+    let ssh = SshConfig::from_settings(&settings)?;
+    let Some(ip) = job.ssh_address(&ssh) else {
+        return Ok(());
+    };
+    let job_id = job.id.clone();
+    tokio::spawn(async move {
+        let _ = install_over_ssh(&job_id, &ip, &ssh, &script).await;
+    });
     Ok(())
 }
 
+/// Pins the host key installed on a new job and makes its ephemeral key
+/// available, before anything connects to it.
+fn remember_keys(job_id: &str, spec: &JobSpec) -> std::io::Result<()> {
+    if let Some(ssh_key) = &spec.ssh_key {
+        ssh::add_identity(job_id, &ssh_key.private_key);
+    }
+    match &spec.host_key {
+        Some(host_key) => ssh::pin(job_id, &host_key.public_key),
+        None => Ok(()),
+    }
+}
+
+/// Generates an ephemeral key to log in to a new job with, unless a key is
+/// already registered with the provider as `ssh_key_name`.
+///
+/// # Errors
+///
+/// Returns an error if the key cannot be generated.
+pub fn ephemeral_key(config: &Config) -> Result<Option<KeyPair>, SshError> {
+    if config.ssh_key_name.is_empty() {
+        KeyPair::generate("ffs").map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
 pub enum ProviderType {
     Hetzner,
     AWS,
@@ -48,7 +593,10 @@ pub enum ProviderType {
 
 #[async_trait]
 pub trait Provider: Send + Sync {
-    async fn start_job(&self, name: &str) -> Result<Job, Box<dyn std::error::Error + Send + Sync>>;
+    async fn start_job(
+        &self,
+        spec: &JobSpec,
+    ) -> Result<Job, Box<dyn std::error::Error + Send + Sync>>;
     async fn get_job(
         &self,
         job_id: &str,
@@ -56,27 +604,92 @@ pub trait Provider: Send + Sync {
     async fn stop_job(&self, job_id: &str)
         -> Result<Job, Box<dyn std::error::Error + Send + Sync>>;
     async fn list_jobs(&self) -> Result<Vec<Job>, Box<dyn std::error::Error + Send + Sync>>;
+    /// Returns the hourly price of a server for the job with the configured type and location.
+    async fn hourly_price(
+        &self,
+        spec: &JobSpec,
+    ) -> Result<Option<HourlyPrice>, Box<dyn std::error::Error + Send + Sync>>;
+    /// Lists every server, volume, key and security group tagged as managed by
+    /// ffs, except persistent volumes.
+    async fn list_resources(
+        &self,
+    ) -> Result<Vec<Resource>, Box<dyn std::error::Error + Send + Sync>>;
+    async fn delete_resource(
+        &self,
+        resource: &Resource,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    /// Lists the persistent volumes created with `--volume`.
+    async fn list_volumes(&self) -> Result<Vec<Volume>, Box<dyn std::error::Error + Send + Sync>>;
+    async fn delete_volume(
+        &self,
+        name: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    /// Provisions a temporary server, runs `install_script` on it and snapshots
+    /// it into an image labelled with `toolchain`. The server is always removed.
+    async fn bake_image(
+        &self,
+        toolchain: &str,
+        install_script: &str,
+    ) -> Result<Image, Box<dyn std::error::Error + Send + Sync>>;
+    async fn list_images(&self) -> Result<Vec<Image>, Box<dyn std::error::Error + Send + Sync>>;
+    async fn delete_image(&self, id: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    /// Prints a file on the job.
     async fn tail(
         &self,
         job_id: &str,
         filename: &str,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let job = self
+            .get_job(job_id)
+            .await?
+            .ok_or_else(|| format!("job {job_id} not found"))?;
+        let ssh = SshConfig::load()?;
+        let address = job
+            .ssh_address(&ssh)
+            .ok_or_else(|| format!("job {job_id} has no address yet"))?;
+        let command = format!("cat {filename}");
+        tokio::task::spawn_blocking(move || {
+            let connection = Connection::open(&job.id, &address, &ssh)?;
+            match connection.stream(&command, &mut std::io::stdout())? {
+                0 => Ok(()),
+                status => Err(SshError::Command { command, status }),
+            }
+        })
+        .await??;
+        Ok(())
+    }
+
+    /// Copies a file or directory from the job to `destination`.
     async fn scp(
         &self,
         job_id: &str,
         filename: &str,
         destination: &str,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let job = self
+            .get_job(job_id)
+            .await?
+            .ok_or_else(|| format!("job {job_id} not found"))?;
+        let ssh = SshConfig::load()?;
+        let address = job
+            .ssh_address(&ssh)
+            .ok_or_else(|| format!("job {job_id} has no address yet"))?;
+        let (filename, destination) = (filename.to_string(), PathBuf::from(destination));
+        tokio::task::spawn_blocking(move || {
+            Connection::open(&job.id, &address, &ssh)?.copy_path(&filename, &destination)
+        })
+        .await??;
+        Ok(())
+    }
 
     async fn install_dependencies(
         &self,
+        job_id: &str,
         ip: &str,
-        key_path: &str,
+        ssh: &SshConfig,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let ip = ip.to_string();
-        let key = key_path.to_string();
-        tokio::task::spawn_blocking(move || install_over_ssh(&ip, &key)).await??;
-        Ok(())
+        install_over_ssh(job_id, ip, ssh, INSTALL_SCRIPT).await
     }
 }
 
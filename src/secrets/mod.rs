@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{Read, Write};
+
+use age::secrecy::Secret;
+use keyring::Entry;
+use toml::Value;
+
+const SECRETS_DIR: &str = ".ffs";
+const SECRETS_FILENAME: &str = "secrets.age";
+const KEYRING_SERVICE: &str = "ffs";
+const PASSPHRASE_ENV: &str = "FFS_PASSPHRASE";
+
+/// Where a secret value is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// The OS keyring (Secret Service on Linux).
+    Keyring,
+    /// A passphrase-encrypted age file at `~/.ffs/secrets.age`.
+    File,
+}
+
+impl Backend {
+    #[must_use]
+    pub const fn scheme(self) -> &'static str {
+        match self {
+            Self::Keyring => "keyring",
+            Self::File => "file",
+        }
+    }
+
+    /// Returns the reference stored in the database in place of the secret.
+    #[must_use]
+    pub fn reference(self, name: &str) -> String {
+        format!("{}:{name}", self.scheme())
+    }
+}
+
+impl std::str::FromStr for Backend {
+    type Err = SecretError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keyring" => Ok(Self::Keyring),
+            "file" => Ok(Self::File),
+            _ => Err(SecretError::UnknownBackend(s.to_string())),
+        }
+    }
+}
+
+/// Error returned when a secret reference cannot be resolved or stored.
+#[derive(Debug)]
+pub enum SecretError {
+    UnknownBackend(String),
+    NotFound(String),
+    Keyring(String),
+    File(String),
+}
+
+impl fmt::Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownBackend(backend) => write!(f, "unknown secret backend `{backend}`"),
+            Self::NotFound(reference) => write!(f, "secret `{reference}` not found"),
+            Self::Keyring(reason) => write!(f, "keyring error: {reason}"),
+            Self::File(reason) => write!(f, "secrets file error: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for SecretError {}
+
+/// Resolves a config value that may reference a secret.
+///
+/// Values of the form `keyring:<name>`, `file:<name>` or `env:<VAR>` are looked up
+/// in the corresponding backend; any other value is returned unchanged.
+///
+/// # Errors
+///
+/// Returns an error if the reference points to a secret that cannot be read.
+pub fn resolve(value: &str) -> Result<String, SecretError> {
+    let Some((scheme, name)) = value.split_once(':') else {
+        return Ok(value.to_string());
+    };
+    match scheme {
+        "keyring" => keyring_get(name),
+        "file" => read_file()?
+            .remove(name)
+            .ok_or_else(|| SecretError::NotFound(value.to_string())),
+        "env" => std::env::var(name).map_err(|_| SecretError::NotFound(value.to_string())),
+        _ => Ok(value.to_string()),
+    }
+}
+
+/// Returns whether `value` is a secret reference rather than a literal value.
+#[must_use]
+pub fn is_reference(value: &str) -> bool {
+    value
+        .split_once(':')
+        .is_some_and(|(scheme, _)| matches!(scheme, "keyring" | "file" | "env"))
+}
+
+/// Stores a secret in the given backend.
+///
+/// # Errors
+///
+/// Returns an error if the backend cannot be written.
+pub fn store(backend: Backend, name: &str, secret: &str) -> Result<(), SecretError> {
+    match backend {
+        Backend::Keyring => keyring_entry(name)?
+            .set_password(secret)
+            .map_err(|e| SecretError::Keyring(e.to_string())),
+        Backend::File => {
+            let mut secrets = read_file()?;
+            secrets.insert(name.to_string(), secret.to_string());
+            write_file(&secrets)
+        }
+    }
+}
+
+/// Removes a secret from the given backend.
+///
+/// # Errors
+///
+/// Returns an error if the backend cannot be written.
+pub fn remove(backend: Backend, name: &str) -> Result<(), SecretError> {
+    match backend {
+        Backend::Keyring => keyring_entry(name)?
+            .delete_password()
+            .map_err(|e| SecretError::Keyring(e.to_string())),
+        Backend::File => {
+            let mut secrets = read_file()?;
+            if secrets.remove(name).is_none() {
+                return Err(SecretError::NotFound(Backend::File.reference(name)));
+            }
+            write_file(&secrets)
+        }
+    }
+}
+
+fn keyring_entry(name: &str) -> Result<Entry, SecretError> {
+    Entry::new(KEYRING_SERVICE, name).map_err(|e| SecretError::Keyring(e.to_string()))
+}
+
+fn keyring_get(name: &str) -> Result<String, SecretError> {
+    keyring_entry(name)?.get_password().map_err(|e| match e {
+        keyring::Error::NoEntry => SecretError::NotFound(Backend::Keyring.reference(name)),
+        other => SecretError::Keyring(other.to_string()),
+    })
+}
+
+fn secrets_file() -> String {
+    let home = std::env::var("HOME").unwrap_or_default();
+    format!("{home}/{SECRETS_DIR}/{SECRETS_FILENAME}")
+}
+
+fn passphrase() -> Result<Secret<String>, SecretError> {
+    let passphrase = match std::env::var(PASSPHRASE_ENV) {
+        Ok(passphrase) => passphrase,
+        Err(_) => rpassword::prompt_password("Secrets passphrase: ")
+            .map_err(|e| SecretError::File(e.to_string()))?,
+    };
+    Ok(Secret::new(passphrase))
+}
+
+fn read_file() -> Result<HashMap<String, String>, SecretError> {
+    let path = secrets_file();
+    let Ok(encrypted) = fs::read(&path) else {
+        return Ok(HashMap::new());
+    };
+    let decryptor = match age::Decryptor::new(&encrypted[..]) {
+        Ok(age::Decryptor::Passphrase(decryptor)) => decryptor,
+        Ok(_) => {
+            return Err(SecretError::File(format!(
+                "{path} is not passphrase-encrypted"
+            )))
+        }
+        Err(e) => return Err(SecretError::File(e.to_string())),
+    };
+    let mut contents = String::new();
+    decryptor
+        .decrypt(&passphrase()?, None)
+        .map_err(|e| SecretError::File(e.to_string()))?
+        .read_to_string(&mut contents)
+        .map_err(|e| SecretError::File(e.to_string()))?;
+    let value = contents
+        .parse::<Value>()
+        .map_err(|e| SecretError::File(e.to_string()))?;
+    Ok(value
+        .as_table()
+        .map(|table| {
+            table
+                .iter()
+                .map(|(k, v)| (k.clone(), v.as_str().unwrap_or_default().to_string()))
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+fn write_file(secrets: &HashMap<String, String>) -> Result<(), SecretError> {
+    let contents = toml::to_string(secrets).map_err(|e| SecretError::File(e.to_string()))?;
+    let mut encrypted = Vec::new();
+    let mut writer = age::Encryptor::with_user_passphrase(passphrase()?)
+        .wrap_output(&mut encrypted)
+        .map_err(|e| SecretError::File(e.to_string()))?;
+    writer
+        .write_all(contents.as_bytes())
+        .map_err(|e| SecretError::File(e.to_string()))?;
+    writer
+        .finish()
+        .map_err(|e| SecretError::File(e.to_string()))?;
+    fs::write(secrets_file(), encrypted).map_err(|e| SecretError::File(e.to_string()))
+}
@@ -0,0 +1,938 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, IsTerminal, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::os::fd::OwnedFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use base64::Engine;
+use ssh2::{CheckResult, ExtendedData, KnownHostFileKind, MethodType, Session};
+
+use crate::config::SshConfig;
+use crate::database::Registry;
+use crate::utils::timestamp;
+
+/// Pause between connection attempts while a job's SSH server comes up.
+const RETRY_INTERVAL: Duration = Duration::from_secs(3);
+
+const KNOWN_HOSTS_DIR: &str = ".ffs/known_hosts";
+const KEYS_DIR: &str = ".ffs/keys";
+
+/// Magic bytes opening a private key in the OpenSSH format.
+const OPENSSH_KEY_MAGIC: &[u8] = b"openssh-key-v1\0";
+
+/// Error returned by SSH operations on a job.
+#[derive(Debug)]
+pub enum SshError {
+    Connect {
+        host: String,
+        reason: String,
+    },
+    Auth {
+        user: String,
+        host: String,
+        reason: String,
+    },
+    Command {
+        command: String,
+        status: i32,
+    },
+    Session(ssh2::Error),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for SshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connect { host, reason } => write!(f, "cannot connect to {host}: {reason}"),
+            Self::Auth { user, host, reason } => {
+                write!(f, "cannot authenticate as {user}@{host}: {reason}")
+            }
+            Self::Command { command, status } => {
+                write!(f, "`{command}` exited with status {status}")
+            }
+            Self::HostKey { host, job_id } => write!(
+                f,
+                "host key of {host} does not match the one pinned for job {job_id}"
+            ),
+            Self::Session(e) => write!(f, "ssh: {e}"),
+            Self::Io(e) => write!(f, "ssh: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for SshError {}
+
+impl From<ssh2::Error> for SshError {
+    fn from(e: ssh2::Error) -> Self {
+        Self::Session(e)
+    }
+}
+
+impl From<std::io::Error> for SshError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Authenticated sessions kept open for the life of the process, keyed by
+/// `job/user@host:port`.
+fn pool() -> &'static Mutex<HashMap<String, Session>> {
+    static POOL: OnceLock<Mutex<HashMap<String, Session>>> = OnceLock::new();
+    POOL.get_or_init(Mutex::default)
+}
+
+/// An authenticated SSH connection to a job. Connections are reused across
+/// operations on the same host until they stop answering keepalives.
+#[derive(Clone)]
+pub struct Connection {
+    session: Session,
+    host: String,
+}
+
+impl Connection {
+    /// Opens a connection to job `job_id` at `host`, or reuses the open one. The
+    /// server must present the host key pinned for the job; a job without one
+    /// has the key it presents first pinned.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the host cannot be reached after `config.retries`
+    /// retries, its host key does not match, or authentication fails.
+    pub fn open(job_id: &str, host: &str, config: &SshConfig) -> Result<Self, SshError> {
+        let key = format!("{job_id}/{}@{host}:{}", config.user, config.port);
+        let pooled = lock(pool()).remove(&key);
+        if let Some(session) = pooled.filter(|session| session.keepalive_send().is_ok()) {
+            lock(pool()).insert(key, session.clone());
+            return Ok(Self {
+                session,
+                host: host.to_string(),
+            });
+        }
+
+        let session = connect_with_retries(job_id, host, config)?;
+        lock(pool()).insert(key, session.clone());
+        Ok(Self {
+            session,
+            host: host.to_string(),
+        })
+    }
+
+    #[must_use]
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Runs a command, returning its exit status and output.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command cannot be run.
+    pub fn exec(&self, command: &str) -> Result<(i32, String), SshError> {
+        let mut channel = self.session.channel_session()?;
+        channel.exec(command)?;
+        let mut output = String::new();
+        channel.read_to_string(&mut output)?;
+        channel.wait_close()?;
+        Ok((channel.exit_status()?, output))
+    }
+
+    /// Runs a command and fails unless it exits successfully.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command cannot be run or exits with a non-zero status.
+    pub fn run(&self, command: &str) -> Result<String, SshError> {
+        match self.exec(command)? {
+            (0, output) => Ok(output),
+            (status, _) => Err(SshError::Command {
+                command: command.to_string(),
+                status,
+            }),
+        }
+    }
+
+    /// Runs a shell script fed through standard input and waits for it to finish.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the script cannot be run or exits with a non-zero status.
+    pub fn run_script(&self, script: &str) -> Result<(), SshError> {
+        let mut channel = self.session.channel_session()?;
+        channel.exec("bash -s")?;
+        channel.write_all(script.as_bytes())?;
+        channel.send_eof()?;
+        std::io::copy(&mut channel, &mut std::io::sink())?;
+        channel.wait_close()?;
+        match channel.exit_status()? {
+            0 => Ok(()),
+            status => Err(SshError::Command {
+                command: "bash -s".to_string(),
+                status,
+            }),
+        }
+    }
+
+    /// Reads a file, returning `None` if it does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command cannot be run.
+    pub fn read_file(&self, path: &str) -> Result<Option<String>, SshError> {
+        let (status, contents) = self.exec(&format!("cat {path}"))?;
+        Ok((status == 0).then_some(contents))
+    }
+
+    /// Runs a command and copies its standard output to `output` as it arrives,
+    /// returning its exit status.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command cannot be run or the output cannot be written.
+    pub fn stream(&self, command: &str, output: &mut impl Write) -> Result<i32, SshError> {
+        let mut channel = self.session.channel_session()?;
+        channel.exec(command)?;
+        std::io::copy(&mut channel, output)?;
+        channel.wait_close()?;
+        Ok(channel.exit_status()?)
+    }
+
+    /// Runs a command and calls `on_line` with each line of its output, standard
+    /// error included, as it arrives, returning its exit status.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command cannot be run or its output cannot be read.
+    pub fn stream_lines(
+        &self,
+        command: &str,
+        mut on_line: impl FnMut(&str),
+    ) -> Result<i32, SshError> {
+        let mut channel = self.session.channel_session()?;
+        channel.handle_extended_data(ExtendedData::Merge)?;
+        channel.exec(command)?;
+        let mut output = BufReader::new(&mut channel);
+        let mut line = Vec::new();
+        while output.read_until(b'\n', &mut line)? > 0 {
+            on_line(String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']));
+            line.clear();
+        }
+        channel.wait_close()?;
+        Ok(channel.exit_status()?)
+    }
+
+    /// Runs a command and writes its standard output to `local_path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command fails or the file cannot be written.
+    pub fn download(&self, command: &str, local_path: &Path) -> Result<(), SshError> {
+        let mut file = File::create(local_path)?;
+        match self.stream(command, &mut file)? {
+            0 => Ok(()),
+            status => Err(SshError::Command {
+                command: command.to_string(),
+                status,
+            }),
+        }
+    }
+
+    /// Runs a command with the contents of `local_path` as its standard input.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or the command fails.
+    pub fn upload(&self, local_path: &Path, command: &str) -> Result<(), SshError> {
+        let mut channel = self.session.channel_session()?;
+        channel.exec(command)?;
+        let mut file = File::open(local_path)?;
+        std::io::copy(&mut file, &mut channel)?;
+        channel.send_eof()?;
+        channel.wait_close()?;
+        match channel.exit_status()? {
+            0 => Ok(()),
+            status => Err(SshError::Command {
+                command: command.to_string(),
+                status,
+            }),
+        }
+    }
+
+    /// Copies a remote file, or a directory recursively, to `local_path`. A file
+    /// copied into an existing local directory keeps its name.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the remote path cannot be read or the local one written.
+    pub fn copy_path(&self, remote_path: &str, local_path: &Path) -> Result<(), SshError> {
+        if self.exec(&format!("test -d {remote_path}"))?.0 != 0 {
+            let target = match Path::new(remote_path).file_name() {
+                Some(name) if local_path.is_dir() => local_path.join(name),
+                _ => local_path.to_path_buf(),
+            };
+            return self.copy_file(remote_path, &target);
+        }
+
+        std::fs::create_dir_all(local_path)?;
+        let mut tar = Command::new("tar")
+            .arg("xf")
+            .arg("-")
+            .arg("-C")
+            .arg(local_path)
+            .stdin(Stdio::piped())
+            .spawn()?;
+        let command = format!("tar cf - -C {remote_path} .");
+        let status = match tar.stdin.as_mut() {
+            Some(stdin) => self.stream(&command, stdin)?,
+            None => return Err(SshError::Io(std::io::Error::other("cannot write to tar"))),
+        };
+        drop(tar.stdin.take());
+        if status != 0 {
+            return Err(SshError::Command { command, status });
+        }
+        if !tar.wait()?.success() {
+            return Err(SshError::Io(std::io::Error::other(format!(
+                "cannot extract {remote_path} into {}",
+                local_path.display()
+            ))));
+        }
+        Ok(())
+    }
+
+    /// Copies a remote file to `local_path` over SCP.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or written.
+    pub fn copy_file(&self, remote_path: &str, local_path: &Path) -> Result<(), SshError> {
+        let (mut channel, _) = self.session.scp_recv(Path::new(remote_path))?;
+        let mut file = File::create(local_path)?;
+        std::io::copy(&mut channel, &mut file)?;
+        channel.send_eof()?;
+        channel.wait_eof()?;
+        channel.close()?;
+        channel.wait_close()?;
+        Ok(())
+    }
+}
+
+/// Opens a new session, retrying `config.retries` times while the host is unreachable.
+fn connect_with_retries(job_id: &str, host: &str, config: &SshConfig) -> Result<Session, SshError> {
+    let mut attempt = 0;
+    loop {
+        match connect(job_id, host, config) {
+            Err(SshError::Connect { .. }) if attempt < config.retries => {
+                attempt += 1;
+                std::thread::sleep(RETRY_INTERVAL);
+            }
+            result => return result,
+        }
+    }
+}
+
+fn connect(job_id: &str, host: &str, config: &SshConfig) -> Result<Session, SshError> {
+    let connect_error = |reason: String| SshError::Connect {
+        host: host.to_string(),
+        reason,
+    };
+    let mut session = Session::new()?;
+    if let Some(command) = proxy_command(job_id, host, config) {
+        let stream = spawn_proxy(&command).map_err(|e| connect_error(e.to_string()))?;
+        session.set_tcp_stream(stream);
+    } else {
+        let addr = (host, config.port)
+            .to_socket_addrs()
+            .map_err(|e| connect_error(e.to_string()))?
+            .next()
+            .ok_or_else(|| connect_error("no address".to_string()))?;
+        let tcp = TcpStream::connect_timeout(&addr, config.connect_timeout)
+            .map_err(|e| connect_error(e.to_string()))?;
+        session.set_tcp_stream(tcp);
+    }
+    session.set_timeout(timeout_ms(config.connect_timeout));
+    // Negotiate the type of the pinned key, or the server may offer another one.
+    if let Some(key_type) = pinned_key_type(job_id) {
+        session.method_pref(MethodType::HostKey, &key_type)?;
+    }
+    session
+        .handshake()
+        .map_err(|e| connect_error(e.to_string()))?;
+    verify_host_key(&session, job_id, host)?;
+    session.set_keepalive(
+        true,
+        u32::try_from(config.keepalive.as_secs()).unwrap_or(u32::MAX),
+    );
+    authenticate(&session, job_id, host, config)?;
+    // Commands such as installs run for a long time; only the setup is bounded.
+    session.set_timeout(0);
+    Ok(session)
+}
+
+/// Returns the command the session to a job is tunnelled through: the configured
+/// proxy command, or OpenSSH forwarding through the jump host.
+fn proxy_command(job_id: &str, host: &str, config: &SshConfig) -> Option<String> {
+    if let Some(command) = &config.proxy_command {
+        return Some(
+            expand_proxy_command(command, job_id)
+                .replace("%h", host)
+                .replace("%p", &config.port.to_string()),
+        );
+    }
+    let target = if host.contains(':') {
+        format!("[{host}]:{}", config.port)
+    } else {
+        format!("{host}:{}", config.port)
+    };
+    config
+        .jump_host
+        .as_ref()
+        .map(|jump_host| format!("ssh -W {target} ssh://{jump_host}"))
+}
+
+/// Substitutes the job id for `%i` in a proxy command, leaving `%h` and `%p`.
+fn expand_proxy_command(command: &str, job_id: &str) -> String {
+    command.replace("%i", job_id)
+}
+
+/// Runs a proxy command with its standard input and output connected to the
+/// returned socket. The command exits once the socket is closed.
+fn spawn_proxy(command: &str) -> std::io::Result<UnixStream> {
+    let (ours, theirs) = UnixStream::pair()?;
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::from(OwnedFd::from(theirs.try_clone()?)))
+        .stdout(Stdio::from(OwnedFd::from(theirs)))
+        .spawn()?;
+    std::thread::spawn(move || child.wait());
+    Ok(ours)
+}
+
+/// Authenticates with the job's ephemeral key, then the first identity of the
+/// agent the server accepts, falling back to the configured key file.
+fn authenticate(
+    session: &Session,
+    job_id: &str,
+    host: &str,
+    config: &SshConfig,
+) -> Result<(), SshError> {
+    let auth_error = |reason: String| SshError::Auth {
+        user: config.user.clone(),
+        host: host.to_string(),
+        reason,
+    };
+    if let Some(private_key) = identity(job_id) {
+        if session
+            .userauth_pubkey_memory(&config.user, None, &private_key, None)
+            .is_ok()
+        {
+            return Ok(());
+        }
+    }
+    if config.agent
+        && std::env::var_os("SSH_AUTH_SOCK").is_some()
+        && authenticate_with_agent(session, &config.user).unwrap_or(false)
+    {
+        return Ok(());
+    }
+    if config.key_path.is_empty() {
+        return Err(auth_error(
+            "no agent identity was accepted and no key is configured in ssh_key_path".to_string(),
+        ));
+    }
+
+    let key_path = Path::new(&config.key_path);
+    let passphrase = match &config.passphrase {
+        Some(passphrase) => Some(passphrase.clone()),
+        None if is_encrypted(key_path)? => Some(passphrase(key_path).map_err(auth_error)?),
+        None => None,
+    };
+    let public_key = PathBuf::from(format!("{}.pub", config.key_path));
+    let result = session.userauth_pubkey_file(
+        &config.user,
+        public_key.exists().then_some(public_key.as_path()),
+        key_path,
+        passphrase.as_deref(),
+    );
+    if result.is_err() && config.passphrase.is_none() {
+        // A mistyped passphrase is asked for again on the next attempt.
+        lock(passphrases()).remove(key_path);
+    }
+    result.map_err(|e| auth_error(e.to_string()))
+}
+
+fn authenticate_with_agent(session: &Session, user: &str) -> Result<bool, ssh2::Error> {
+    let mut agent = session.agent()?;
+    agent.connect()?;
+    agent.list_identities()?;
+    for identity in agent.identities()? {
+        if agent.userauth(user, &identity).is_ok() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Returns whether a private key file is protected by a passphrase, in either
+/// the OpenSSH or the legacy PEM format.
+fn is_encrypted(key_path: &Path) -> Result<bool, SshError> {
+    let contents = std::fs::read_to_string(key_path)?;
+    let pem_encrypted = contents.lines().any(|line| {
+        (line.starts_with("-----") || line.starts_with("Proc-Type:")) && line.contains("ENCRYPTED")
+    });
+    if pem_encrypted {
+        return Ok(true);
+    }
+    let body: String = contents
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    let Ok(decoded) = base64::engine::general_purpose::STANDARD.decode(body) else {
+        return Ok(false);
+    };
+    // An OpenSSH key starts with its magic, then the length-prefixed cipher name.
+    let Some(rest) = decoded.strip_prefix(OPENSSH_KEY_MAGIC) else {
+        return Ok(false);
+    };
+    let cipher = rest.get(..4).and_then(|len| {
+        let len = u32::from_be_bytes(len.try_into().ok()?) as usize;
+        rest.get(4..4 + len)
+    });
+    Ok(cipher.is_some_and(|cipher| cipher != b"none"))
+}
+
+/// Ephemeral private keys of jobs, by job id.
+fn identities() -> &'static Mutex<HashMap<String, String>> {
+    static IDENTITIES: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
+    IDENTITIES.get_or_init(Mutex::default)
+}
+
+/// Returns the ephemeral private key of a job, from this process or the registry.
+fn identity(job_id: &str) -> Option<String> {
+    if let Some(private_key) = lock(identities()).get(job_id) {
+        return Some(private_key.clone());
+    }
+    let private_key = Registry::load().ok()?.find(job_id)?.ssh_key.clone()?;
+    lock(identities()).insert(job_id.to_string(), private_key.clone());
+    Some(private_key)
+}
+
+/// Makes the ephemeral private key of a job just created available to the
+/// connections of this process, before the job is recorded in the registry.
+pub fn add_identity(job_id: &str, private_key: &str) {
+    lock(identities()).insert(job_id.to_string(), private_key.to_string());
+}
+
+/// Writes the ephemeral private key of a job to a file only the user can read,
+/// for tools such as OpenSSH, returning `None` if the job has none.
+///
+/// # Errors
+///
+/// Returns an error if the key file cannot be written.
+///
+/// # Panics
+///
+/// Panics if the HOME environment variable is not set.
+pub fn identity_file(job_id: &str) -> std::io::Result<Option<PathBuf>> {
+    let Some(private_key) = identity(job_id) else {
+        return Ok(None);
+    };
+    let path = key_file(job_id);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&path)?;
+    file.write_all(private_key.as_bytes())?;
+    Ok(Some(path))
+}
+
+fn key_file(job_id: &str) -> PathBuf {
+    let home = std::env::var("HOME").unwrap();
+    PathBuf::from(format!("{home}/{KEYS_DIR}/{job_id}"))
+}
+
+/// Passphrases entered for encrypted keys, kept so each key is asked for once.
+fn passphrases() -> &'static Mutex<HashMap<PathBuf, String>> {
+    static PASSPHRASES: OnceLock<Mutex<HashMap<PathBuf, String>>> = OnceLock::new();
+    PASSPHRASES.get_or_init(Mutex::default)
+}
+
+fn passphrase(key_path: &Path) -> Result<String, String> {
+    let mut passphrases = lock(passphrases());
+    if let Some(passphrase) = passphrases.get(key_path) {
+        return Ok(passphrase.clone());
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(format!(
+            "{} is encrypted: add it to ssh-agent or set ssh.passphrase",
+            key_path.display()
+        ));
+    }
+    let passphrase = rpassword::prompt_password(format!("Passphrase for {}: ", key_path.display()))
+        .map_err(|e| e.to_string())?;
+    passphrases.insert(key_path.to_path_buf(), passphrase.clone());
+    Ok(passphrase)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+fn verify_host_key(session: &Session, job_id: &str, host: &str) -> Result<(), SshError> {
+    let (key, key_type) = session.host_key().ok_or_else(|| SshError::HostKey {
+        host: host.to_string(),
+        job_id: job_id.to_string(),
+    })?;
+    let path = known_hosts_path(job_id);
+    let alias = host_alias(job_id);
+    let mut known_hosts = session.known_hosts()?;
+    if !path.exists() {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        known_hosts.add(&alias, key, "ffs", key_type.into())?;
+        known_hosts.write_file(&path, KnownHostFileKind::OpenSSH)?;
+        return Ok(());
+    }
+    known_hosts.read_file(&path, KnownHostFileKind::OpenSSH)?;
+    match known_hosts.check(&alias, key) {
+        CheckResult::Match => Ok(()),
+        _ => Err(SshError::HostKey {
+            host: host.to_string(),
+            job_id: job_id.to_string(),
+        }),
+    }
+}
+
+/// Returns the type of the host key pinned for a job, e.g. `ssh-ed25519`.
+fn pinned_key_type(job_id: &str) -> Option<String> {
+    let known_hosts = std::fs::read_to_string(known_hosts_path(job_id)).ok()?;
+    known_hosts
+        .lines()
+        .find_map(|line| line.split_whitespace().nth(1))
+        .map(str::to_string)
+}
+
+fn timeout_ms(timeout: Duration) -> u32 {
+    u32::try_from(timeout.as_millis()).unwrap_or(u32::MAX)
+}
+
+/// Waits until the job accepts SSH connections, trying up to `attempts` times.
+///
+/// # Errors
+///
+/// Returns an error if the job is still unreachable after the last attempt.
+pub async fn wait_until_reachable(
+    job_id: &str,
+    host: &str,
+    config: &SshConfig,
+    attempts: u32,
+    interval: Duration,
+) -> Result<Connection, Box<dyn std::error::Error + Send + Sync>> {
+    let mut last_error = None;
+    for attempt in 1..=attempts {
+        let (job_id, host, config) = (job_id.to_string(), host.to_string(), config.clone());
+        match tokio::task::spawn_blocking(move || Connection::open(&job_id, &host, &config)).await?
+        {
+            Ok(connection) => return Ok(connection),
+            Err(e) => last_error = Some(e),
+        }
+        if attempt < attempts {
+            tokio::time::sleep(interval).await;
+        }
+    }
+    Err(match last_error {
+        Some(e) => Box::new(e),
+        None => format!("job at {host} did not accept SSH connections").into(),
+    })
+}
+
+/// An ed25519 keypair generated for a job before it exists: the host key its
+/// SSH server presents, or the ephemeral key ffs logs in with.
+#[derive(Debug, Clone)]
+pub struct KeyPair {
+    /// Private key in OpenSSH format.
+    pub private_key: String,
+    /// Public key as a `type base64 comment` line.
+    pub public_key: String,
+}
+
+impl KeyPair {
+    /// Generates an ed25519 keypair with `ssh-keygen`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `ssh-keygen` cannot be run or fails.
+    pub fn generate(comment: &str) -> Result<Self, SshError> {
+        let dir =
+            std::env::temp_dir().join(format!("ffs-key-{}-{}", std::process::id(), timestamp()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("key");
+        let status = Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-C", comment, "-f"])
+            .arg(&path)
+            .stdin(Stdio::null())
+            .status();
+        let key = match status {
+            Ok(status) if status.success() => Ok(Self {
+                private_key: std::fs::read_to_string(&path)?,
+                public_key: std::fs::read_to_string(path.with_extension("pub"))?
+                    .trim()
+                    .to_string(),
+            }),
+            Ok(status) => Err(SshError::Command {
+                command: "ssh-keygen".to_string(),
+                status: status.code().unwrap_or(-1),
+            }),
+            Err(e) => Err(SshError::Io(e)),
+        };
+        let _ = std::fs::remove_dir_all(&dir);
+        key
+    }
+}
+
+/// A local port forwarded to a port reachable from the job, written
+/// `LOCAL:REMOTE` or `LOCAL:HOST:REMOTE`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Forward {
+    pub local_port: u16,
+    /// Host the job connects to, `localhost` unless given.
+    pub remote_host: String,
+    pub remote_port: u16,
+}
+
+impl std::str::FromStr for Forward {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let port = |port: &str| {
+            port.parse::<u16>()
+                .ok()
+                .filter(|&port| port > 0)
+                .ok_or_else(|| format!("invalid port `{port}` in `{s}`"))
+        };
+        match s.split(':').collect::<Vec<_>>()[..] {
+            [local, remote] => Ok(Self {
+                local_port: port(local)?,
+                remote_host: "localhost".to_string(),
+                remote_port: port(remote)?,
+            }),
+            [local, host, remote] if !host.is_empty() => Ok(Self {
+                local_port: port(local)?,
+                remote_host: host.to_string(),
+                remote_port: port(remote)?,
+            }),
+            _ => Err(format!(
+                "invalid forward `{s}`, expected LOCAL:REMOTE or LOCAL:HOST:REMOTE"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Forward {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "localhost:{} -> {}:{}",
+            self.local_port, self.remote_host, self.remote_port
+        )
+    }
+}
+
+/// Pause while neither side of a forwarded connection has data.
+const FORWARD_IDLE: Duration = Duration::from_millis(5);
+
+/// Listens on the local port of `forward` and tunnels every connection to the
+/// remote port through a direct-tcpip channel. Each connection gets its own
+/// session, opened with retries, so a dropped SSH connection is re-established
+/// by the next local connection. Runs until the listener fails.
+///
+/// # Errors
+///
+/// Returns an error if the local port cannot be listened on.
+pub fn forward(
+    job_id: &str,
+    host: &str,
+    config: &SshConfig,
+    forward: &Forward,
+) -> Result<(), SshError> {
+    let listener = TcpListener::bind(("127.0.0.1", forward.local_port))?;
+    for local in listener.incoming() {
+        let local = local?;
+        let (job_id, host, config, forward) = (
+            job_id.to_string(),
+            host.to_string(),
+            config.clone(),
+            forward.clone(),
+        );
+        std::thread::spawn(move || {
+            let tunnelled = connect_with_retries(&job_id, &host, &config)
+                .and_then(|session| tunnel(&session, local, &forward));
+            if let Err(e) = tunnelled {
+                eprintln!("{forward}: {e}");
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Copies data both ways between a local connection and a direct-tcpip channel
+/// until either side closes. The session is used without blocking so one thread
+/// can serve both directions.
+fn tunnel(session: &Session, mut local: TcpStream, forward: &Forward) -> Result<(), SshError> {
+    let mut channel =
+        session.channel_direct_tcpip(&forward.remote_host, forward.remote_port, None)?;
+    session.set_blocking(false);
+    local.set_nonblocking(true)?;
+    let mut buf = [0; 16 * 1024];
+    loop {
+        let mut idle = true;
+        match local.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                write_all(&mut channel, &buf[..n])?;
+                idle = false;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e.into()),
+        }
+        match channel.read(&mut buf) {
+            Ok(0) if channel.eof() => break,
+            Ok(0) => {}
+            Ok(n) => {
+                write_all(&mut local, &buf[..n])?;
+                idle = false;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e.into()),
+        }
+        if idle {
+            std::thread::sleep(FORWARD_IDLE);
+        }
+    }
+    let _ = channel.close();
+    Ok(())
+}
+
+/// Writes all of `data` to a non-blocking writer.
+fn write_all(writer: &mut impl Write, mut data: &[u8]) -> std::io::Result<()> {
+    while !data.is_empty() {
+        match writer.write(data) {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(n) => data = &data[n..],
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(FORWARD_IDLE);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Returns the ffs-managed known_hosts file of a job.
+///
+/// # Panics
+///
+/// Panics if the HOME environment variable is not set.
+#[must_use]
+pub fn known_hosts_path(job_id: &str) -> PathBuf {
+    let home = std::env::var("HOME").unwrap();
+    PathBuf::from(format!("{home}/{KNOWN_HOSTS_DIR}/{job_id}"))
+}
+
+/// Name a job's host key is pinned under, so it stays valid whatever address
+/// the job has and is never confused with a previous job at the same address.
+#[must_use]
+pub fn host_alias(job_id: &str) -> String {
+    format!("ffs-{job_id}")
+}
+
+/// Pins `public_key` as the host key of a job, replacing any previous one.
+///
+/// # Errors
+///
+/// Returns an error if the known_hosts file cannot be written.
+pub fn pin(job_id: &str, public_key: &str) -> std::io::Result<()> {
+    let path = known_hosts_path(job_id);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, format!("{} {public_key}\n", host_alias(job_id)))
+}
+
+/// Removes the pinned host key and the ephemeral key file of a job that no
+/// longer exists.
+///
+/// # Errors
+///
+/// Returns an error if a file exists but cannot be removed.
+///
+/// # Panics
+///
+/// Panics if the HOME environment variable is not set.
+pub fn forget(job_id: &str) -> std::io::Result<()> {
+    lock(identities()).remove(job_id);
+    for path in [known_hosts_path(job_id), key_file(job_id)] {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Builds an OpenSSH command logging in to job `job_id` as configured: with the
+/// job's ephemeral key or `ssh_key_path`, the configured user and port, through
+/// the proxy command or jump host if any, and verifying the pinned host key
+/// (pinning the first key it sees if there is none yet). Further options, the
+/// job's address and the remote command are left to the caller.
+///
+/// # Errors
+///
+/// Returns an error if the job's ephemeral key cannot be written for OpenSSH.
+pub fn openssh_command(job_id: &str, config: &SshConfig) -> std::io::Result<Command> {
+    let mut command = Command::new("ssh");
+    command
+        .arg("-o")
+        .arg(format!(
+            "UserKnownHostsFile={}",
+            known_hosts_path(job_id).display()
+        ))
+        .arg("-o")
+        .arg(format!("HostKeyAlias={}", host_alias(job_id)))
+        .args(["-o", "StrictHostKeyChecking=accept-new"])
+        .arg("-l")
+        .arg(&config.user)
+        .arg("-p")
+        .arg(config.port.to_string());
+    if let Some(identity) = identity_file(job_id)? {
+        command
+            .arg("-i")
+            .arg(identity)
+            .args(["-o", "IdentitiesOnly=yes"]);
+    } else if !config.key_path.is_empty() {
+        command.arg("-i").arg(&config.key_path);
+    }
+    if let Some(proxy_command) = &config.proxy_command {
+        command.arg("-o").arg(format!(
+            "ProxyCommand={}",
+            expand_proxy_command(proxy_command, job_id)
+        ));
+    } else if let Some(jump_host) = &config.jump_host {
+        command.arg("-J").arg(jump_host);
+    }
+    Ok(command)
+}
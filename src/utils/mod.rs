@@ -1,4 +1,4 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// Returns the current Unix timestamp in seconds.
 ///
@@ -12,3 +12,25 @@ pub fn timestamp() -> u64 {
         .unwrap()
         .as_secs()
 }
+
+/// Parses a duration such as `90s`, `30m`, `12h` or `2d`; a bare number is in seconds.
+///
+/// # Errors
+///
+/// Returns an error if the number or the unit is invalid.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number = number
+        .parse::<u64>()
+        .map_err(|_| format!("invalid duration `{s}`"))?;
+    let seconds = match unit {
+        "" | "s" => number,
+        "m" => number * 60,
+        "h" => number * 60 * 60,
+        "d" => number * 60 * 60 * 24,
+        _ => return Err(format!("invalid duration unit `{unit}` in `{s}`")),
+    };
+    Ok(Duration::from_secs(seconds))
+}